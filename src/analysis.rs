@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use crate::diff::ConfigDiff;
+use crate::error::{Error, Result};
+use crate::kconfig::Kconfig;
+use crate::trace::Prediction;
+
+
+// Top-level directory of a target, relative to the tree: "drivers", "fs",
+// "arch"... or "." for top-level files (vmlinux, modules.order...).
+pub fn subsystem(target: &str) -> &str {
+    match target.find('/') {
+        Some(slash) => &target[..slash],
+        None => ".",
+    }
+}
+
+fn count<'a>(targets: impl Iterator<Item = &'a String>)
+             -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for target in targets {
+        *counts.entry(subsystem(target).to_string()).or_insert(0) += 1;
+    }
+    counts
+}
+
+// Prediction of an incremental build, per subsystem.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Breakdown {
+    pub predicted: BTreeMap<String, usize>,
+    pub rebuilt: BTreeMap<String, usize>,
+    pub false_positives: BTreeMap<String, usize>,
+    pub false_negatives: BTreeMap<String, usize>,
+}
+
+impl Breakdown {
+
+    pub fn new(prediction: &Prediction) -> Self {
+        Self {
+            predicted: count(prediction.predicted.iter()),
+            rebuilt: count(prediction.rebuilt.iter()),
+            false_positives: count(prediction.false_positives.iter()),
+            false_negatives: count(prediction.false_negatives.iter()),
+        }
+    }
+
+    pub fn subsystems(&self) -> BTreeSet<&str> {
+        self.predicted.keys().chain(self.rebuilt.keys())
+            .map(|s| s.as_str())
+            .collect()
+    }
+
+    // One line per subsystem: name, predicted, rebuilt, false positives and
+    // false negatives, separated by tabs.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let get = |counts: &BTreeMap<String, usize>, s: &str|
+            counts.get(s).copied().unwrap_or(0);
+        let mut table = "# subsystem\tpredicted\trebuilt\tfalse positives\t\
+                         false negatives\n".to_string();
+        for s in self.subsystems() {
+            table.push_str(&format!("{}\t{}\t{}\t{}\t{}\n", s,
+                                    get(&self.predicted, s),
+                                    get(&self.rebuilt, s),
+                                    get(&self.false_positives, s),
+                                    get(&self.false_negatives, s)));
+        }
+        fs::write(path, table).map_err(|err| Error::io(path, err))
+    }
+}
+
+// Knows which options decide whether, or how, an object is built.
+pub trait Owners {
+    // Options that may explain a rebuild of `object` (path relative to the
+    // tree), grouped from the most to the least specific.
+    fn owners(&self, object: &str) -> Vec<Vec<String>>;
+}
+
+// Heuristic owners: the options defined in the Kconfig files of the
+// directory of an object, then of its parents.
+#[derive(Debug, Clone, Default)]
+pub struct KconfigDirs {
+    // Directory relative to the tree ("" for the top) -> options.
+    dirs: BTreeMap<String, Vec<String>>,
+}
+
+impl KconfigDirs {
+
+    pub fn new(kconfig: &Kconfig) -> Self {
+        let srctree = kconfig.files.first()
+            .and_then(|root| root.parent())
+            .unwrap_or_else(|| Path::new(""));
+        let mut dirs: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for symbol in kconfig.symbols.values() {
+            for definition in &symbol.definitions {
+                let dir = definition.file.parent()
+                    .and_then(|d| d.strip_prefix(srctree).ok())
+                    .map(|d| d.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let options = dirs.entry(dir).or_default();
+                if !options.contains(&symbol.name) {
+                    options.push(symbol.name.clone());
+                }
+            }
+        }
+        Self { dirs }
+    }
+}
+
+impl Owners for KconfigDirs {
+    fn owners(&self, object: &str) -> Vec<Vec<String>> {
+        let mut levels = Vec::new();
+        let mut dir = object;
+        while let Some(slash) = dir.rfind('/') {
+            dir = &dir[..slash];
+            if let Some(options) = self.dirs.get(dir) {
+                levels.push(options.clone());
+            }
+        }
+        if let Some(options) = self.dirs.get("") {
+            levels.push(options.clone());
+        }
+        levels
+    }
+}
+
+// Owners from several sources: the levels of the first, then the second's.
+impl<A: Owners, B: Owners> Owners for (A, B) {
+    fn owners(&self, object: &str) -> Vec<Vec<String>> {
+        let mut levels = self.0.owners(object);
+        levels.extend(self.1.owners(object));
+        levels
+    }
+}
+
+impl<T: Owners> Owners for Option<T> {
+    fn owners(&self, object: &str) -> Vec<Vec<String>> {
+        self.as_ref().map(|owners| owners.owners(object)).unwrap_or_default()
+    }
+}
+
+// Rebuilt targets attributed to the changed options that explain them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attribution {
+    // Option -> targets.
+    pub options: BTreeMap<String, Vec<String>>,
+    pub unattributed: Vec<String>,
+}
+
+impl Attribution {
+
+    // Each target goes to the changed options among its most specific owners
+    // that have any.
+    pub fn new(diff: &ConfigDiff, targets: &[String], owners: &dyn Owners)
+               -> Self {
+        let changed: BTreeSet<&str> = diff.changed.keys()
+            .chain(diff.added.keys())
+            .chain(diff.removed.keys())
+            .map(|name| name.as_str())
+            .collect();
+        let mut attribution = Self::default();
+        for target in targets {
+            let level = owners.owners(target).into_iter()
+                .map(|level| level.into_iter()
+                     .filter(|o| changed.contains(o.as_str()))
+                     .collect::<Vec<String>>())
+                .find(|level| !level.is_empty());
+            match level {
+                Some(options) => for option in options {
+                    attribution.options.entry(option).or_default()
+                        .push(target.clone());
+                },
+                None => attribution.unattributed.push(target.clone()),
+            }
+        }
+        attribution
+    }
+
+    // Options by decreasing number of targets.
+    pub fn ranking(&self) -> Vec<(&str, usize)> {
+        let mut ranking: Vec<(&str, usize)> = self.options.iter()
+            .map(|(option, targets)| (option.as_str(), targets.len()))
+            .collect();
+        ranking.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        ranking
+    }
+
+    // `OPTION count` lines, most expensive first, each followed by its
+    // targets indented, then the unattributed targets.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut report = String::new();
+        for (option, n) in self.ranking() {
+            report.push_str(&format!("{} {}\n", option, n));
+            for target in &self.options[option] {
+                report.push_str(&format!("  {}\n", target));
+            }
+        }
+        if !self.unattributed.is_empty() {
+            report.push_str(&format!("(unattributed) {}\n",
+                                     self.unattributed.len()));
+            for target in &self.unattributed {
+                report.push_str(&format!("  {}\n", target));
+            }
+        }
+        fs::write(path, report).map_err(|err| Error::io(path, err))
+    }
+}
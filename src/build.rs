@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use crate::error::{Error, Result};
+
+
+// How make is invoked on the kernel tree.
+//
+//   let options = BuildOptions::new().jobs(8).arch("arm64")
+//       .cross_compile("aarch64-linux-gnu-").target("Image");
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildOptions {
+    jobs: usize,
+    arch: Option<String>,
+    cross_compile: Option<String>,
+    out_dir: Option<PathBuf>,
+    llvm: bool,
+    kcflags: Option<String>,
+    targets: Vec<String>,
+    env: BTreeMap<String, String>,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            jobs: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            arch: None,
+            cross_compile: None,
+            out_dir: None,
+            llvm: false,
+            kcflags: None,
+            targets: Vec::new(),
+            env: BTreeMap::new(),
+        }
+    }
+}
+
+impl BuildOptions {
+
+    // Defaults to as many jobs as available CPUs and the default target.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    pub fn arch(mut self, arch: &str) -> Self {
+        self.arch = Some(arch.to_string());
+        self
+    }
+
+    pub fn cross_compile(mut self, prefix: &str) -> Self {
+        self.cross_compile = Some(prefix.to_string());
+        self
+    }
+
+    // O=: relative paths are relative to the kernel tree.
+    pub fn out_dir(mut self, dir: &Path) -> Self {
+        self.out_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    pub fn llvm(mut self, llvm: bool) -> Self {
+        self.llvm = llvm;
+        self
+    }
+
+    pub fn kcflags(mut self, flags: &str) -> Self {
+        self.kcflags = Some(flags.to_string());
+        self
+    }
+
+    pub fn target(mut self, target: &str) -> Self {
+        self.targets.push(target.to_string());
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.env.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn get_jobs(&self) -> usize {
+        self.jobs
+    }
+
+    pub fn get_arch(&self) -> Option<&str> {
+        self.arch.as_deref()
+    }
+
+    pub fn get_targets(&self) -> &[String] {
+        &self.targets
+    }
+
+    pub fn get_out_dir(&self) -> Option<&Path> {
+        self.out_dir.as_deref()
+    }
+
+    // Variable assignments given to make, without -j and targets.
+    pub fn make_variables(&self) -> Vec<String> {
+        let mut vars = Vec::new();
+        if let Some(arch) = &self.arch {
+            vars.push(format!("ARCH={}", arch));
+        }
+        if let Some(prefix) = &self.cross_compile {
+            vars.push(format!("CROSS_COMPILE={}", prefix));
+        }
+        if let Some(dir) = &self.out_dir {
+            vars.push(format!("O={}", dir.display()));
+        }
+        if self.llvm {
+            vars.push("LLVM=1".to_string());
+        }
+        if let Some(flags) = &self.kcflags {
+            vars.push(format!("KCFLAGS={}", flags));
+        }
+        vars
+    }
+
+    // `make` in `source` with the variables and environment, but neither -j
+    // nor targets: callers (dry runs, olddefconfig...) add their own.
+    pub fn make(&self, source: &str) -> Command {
+        let mut make = Command::new("make");
+        make.args(self.make_variables())
+            .envs(&self.env)
+            .current_dir(source);
+        make
+    }
+}
+
+
+// Outcome of a kernel build, as measured by /usr/bin/time. The make output
+// is kept in `stdout`/`stderr`, outside the kernel tree.
+#[derive(Debug, Clone)]
+pub struct BuildResult {
+    pub source: PathBuf,
+    pub jobs: usize,
+    pub wall: Duration,
+    pub user: Duration,
+    pub sys: Duration,
+    pub max_rss_kb: u64,
+    // None when make was killed by a signal.
+    pub exit_code: Option<i32>,
+    pub stdout: PathBuf,
+    pub stderr: PathBuf,
+}
+
+impl BuildResult {
+
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    // Turns a failed build into an Error::Build.
+    pub fn check(self) -> Result<Self> {
+        if self.success() {
+            Ok(self)
+        }else {
+            Err(Error::Build { source: self.source, status: self.exit_code })
+        }
+    }
+}
+
+fn log_file(path: &Path) -> Result<fs::File> {
+    fs::File::create(path).map_err(|err| Error::io(path, err))
+}
+
+fn seconds(field: Option<&str>) -> Option<Duration> {
+    field?.parse::<f64>().ok().filter(|s| *s >= 0.0)
+        .map(Duration::from_secs_f64)
+}
+
+// Runs make in `source` as described by `options`. Logs and timings are
+// written in `logdir` (created if needed) as build.log, error.log and time.
+// A build that fails is not an error: check `success()` or use `check()`.
+pub fn build(source: &str, options: &BuildOptions, logdir: &Path)
+             -> Result<BuildResult> {
+    fs::create_dir_all(logdir).map_err(|err| Error::io(logdir, err))?;
+    // make runs in `source`: relative log paths would end up in the tree.
+    let logdir = &fs::canonicalize(logdir).map_err(|err| Error::io(logdir, err))?;
+    let stdout = logdir.join("build.log");
+    let stderr = logdir.join("error.log");
+    let time = logdir.join("time");
+
+    let status = Command::new("/usr/bin/time")
+        .arg("-o").arg(&time)
+        .args(["--format=%e %U %S %M %x", "make"])
+        .arg(format!("-j{}", options.jobs))
+        .args(options.make_variables())
+        .args(&options.targets)
+        .envs(&options.env)
+        .current_dir(source)
+        .stdout(Stdio::from(log_file(&stdout)?))
+        .stderr(Stdio::from(log_file(&stderr)?))
+        .status()
+        .map_err(|err| Error::io(Path::new("/usr/bin/time"), err))?;
+
+    // When make is killed, time writes a "Command terminated by signal"
+    // line before the formatted one.
+    let measures = fs::read_to_string(&time)
+        .map_err(|err| Error::io(&time, err))?;
+    let line = measures.lines().last().unwrap_or_default();
+    let mut fields = line.split_whitespace();
+    let malformed = || Error::parse(&time, measures.lines().count(),
+                                    format!("unexpected measures: {}", line));
+
+    let wall = seconds(fields.next()).ok_or_else(malformed)?;
+    let user = seconds(fields.next()).ok_or_else(malformed)?;
+    let sys = seconds(fields.next()).ok_or_else(malformed)?;
+    let max_rss_kb = fields.next().and_then(|f| f.parse().ok())
+        .ok_or_else(malformed)?;
+    let exit_code = if measures.contains("terminated by signal") {
+        None
+    }else {
+        fields.next().and_then(|f| f.parse().ok()).or(status.code())
+    };
+
+    Ok(BuildResult {
+        source: PathBuf::from(source),
+        jobs: options.jobs,
+        wall,
+        user,
+        sys,
+        max_rss_kb,
+        exit_code,
+        stdout,
+        stderr,
+    })
+}
@@ -0,0 +1,226 @@
+use std::env;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use crate::download::{Downloader, KernelVersion};
+use crate::error::{Error, Result};
+use crate::extract_tar;
+
+
+// A cached release: <root>/<version>-<sha256>/ holding the tarball and,
+// once extracted, the pristine tree under linux/.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedKernel {
+    pub version: KernelVersion,
+    pub sha256: String,
+    pub path: PathBuf,
+    pub tarball: Option<PathBuf>,
+    pub tree: Option<PathBuf>,
+}
+
+impl CachedKernel {
+
+    fn from_dir(path: PathBuf) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let (version, sha256) = name.rsplit_once('-')?;
+        let version = KernelVersion::parse(version)?;
+        let tarball = path.join(version.tarball());
+        let tree = path.join("linux");
+        Some(Self {
+            version,
+            sha256: sha256.to_string(),
+            tarball: Some(tarball).filter(|t| t.is_file()),
+            tree: Some(tree).filter(|t| t.is_dir()),
+            path,
+        })
+    }
+
+    // Bytes used on disk, symlinks not followed.
+    pub fn size(&self) -> Result<u64> {
+        dir_size(&self.path)
+    }
+}
+
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path).map_err(|err| Error::io(path, err))? {
+        let entry = entry.map_err(|err| Error::io(path, err))?;
+        let meta = entry.metadata().map_err(|err| Error::io(&entry.path(), err))?;
+        size += if meta.is_dir() { dir_size(&entry.path())? } else { meta.len() };
+    }
+    Ok(size)
+}
+
+fn copy_tree(src: &Path, dst: &Path) -> Result<()> {
+    fs::create_dir_all(dst).map_err(|err| Error::io(dst, err))?;
+    for entry in fs::read_dir(src).map_err(|err| Error::io(src, err))? {
+        let entry = entry.map_err(|err| Error::io(src, err))?;
+        let from = entry.path();
+        let to = dst.join(entry.file_name());
+        let kind = entry.file_type().map_err(|err| Error::io(&from, err))?;
+        if kind.is_dir() {
+            copy_tree(&from, &to)?;
+        }else if kind.is_symlink() {
+            let target = fs::read_link(&from).map_err(|err| Error::io(&from, err))?;
+            symlink(target, &to).map_err(|err| Error::io(&to, err))?;
+        }else {
+            fs::copy(&from, &to).map_err(|err| Error::io(&from, err))?;
+        }
+    }
+    Ok(())
+}
+
+// $XDG_CACHE_HOME/lmutib, or ~/.cache/lmutib.
+pub fn cache_home() -> PathBuf {
+    let cache = env::var_os("XDG_CACHE_HOME").filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            Path::new(&env::var_os("HOME").unwrap_or_default()).join(".cache")
+        });
+    cache.join("lmutib")
+}
+
+// Where releases are cached: <cache home>/kernels.
+pub fn default_root() -> PathBuf {
+    cache_home().join("kernels")
+}
+
+// Kernel releases downloaded once and reused by every experiment.
+//
+//   let cache = Cache::new();
+//   cache.install("5.13", Path::new("/home/linux-5.13"), |_, _| ())?;
+#[derive(Debug, Clone)]
+pub struct Cache {
+    root: PathBuf,
+    downloader: Downloader,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::at(&default_root())
+    }
+}
+
+impl Cache {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn at(root: &Path) -> Self {
+        Self { root: root.to_path_buf(), downloader: Downloader::new() }
+    }
+
+    // Where missing releases are fetched from. Its directory is ignored.
+    pub fn downloader(mut self, downloader: Downloader) -> Self {
+        self.downloader = downloader;
+        self
+    }
+
+    pub fn get_root(&self) -> &Path {
+        &self.root
+    }
+
+    // Cached releases, sorted by version.
+    pub fn list(&self) -> Result<Vec<CachedKernel>> {
+        if !self.root.is_dir() {
+            return Ok(Vec::new());
+        }
+        let entries = fs::read_dir(&self.root)
+            .map_err(|err| Error::io(&self.root, err))?;
+        let mut kernels: Vec<CachedKernel> = entries.flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_dir())
+            .filter_map(CachedKernel::from_dir)
+            .collect();
+        kernels.sort_by(|a, b| a.version.cmp(&b.version)
+                        .then_with(|| a.sha256.cmp(&b.sha256)));
+        Ok(kernels)
+    }
+
+    pub fn find(&self, version: &KernelVersion) -> Result<Option<CachedKernel>> {
+        Ok(self.list()?.into_iter()
+           .find(|k| k.version == *version
+                 && (k.tarball.is_some() || k.tree.is_some())))
+    }
+
+    fn parse(version: &str) -> Result<KernelVersion> {
+        KernelVersion::parse(version)
+            .ok_or_else(|| Error::download(version, "not a kernel version"))
+    }
+
+    // The cached release of `version`, downloading its tarball if needed.
+    pub fn tarball(&self, version: &str,
+                   progress: impl FnMut(u64, Option<u64>))
+                   -> Result<CachedKernel> {
+        let parsed = Self::parse(version)?;
+        if let Some(kernel) = self.find(&parsed)? {
+            return Ok(kernel);
+        }
+        // Partial downloads stay there, to be resumed next time.
+        let downloads = self.root.join(".downloads");
+        let download = self.downloader.clone().dir(&downloads)
+            .fetch(version, progress)?;
+        let path = self.root.join(format!("{}-{}", parsed, download.sha256));
+        fs::create_dir_all(&path).map_err(|err| Error::io(&path, err))?;
+        let tarball = path.join(parsed.tarball());
+        fs::rename(&download.path, &tarball)
+            .map_err(|err| Error::io(&download.path, err))?;
+        CachedKernel::from_dir(path.clone())
+            .ok_or_else(|| Error::io(&path, std::io::ErrorKind::InvalidData.into()))
+    }
+
+    // The pristine source tree of `version`, extracted once. It must not be
+    // built in: see install.
+    pub fn tree(&self, version: &str, progress: impl FnMut(u64, Option<u64>))
+                -> Result<PathBuf> {
+        let kernel = self.tarball(version, progress)?;
+        if let Some(tree) = kernel.tree {
+            return Ok(tree);
+        }
+        let tarball = kernel.tarball.unwrap_or_default();
+        // Extracted aside first, so that an interrupted extraction is never
+        // taken for a tree.
+        let scratch = kernel.path.join(".extract");
+        if scratch.exists() {
+            fs::remove_dir_all(&scratch).map_err(|err| Error::io(&scratch, err))?;
+        }
+        let top = extract_tar(&tarball.to_string_lossy(),
+                              &scratch.to_string_lossy())?;
+        let tree = kernel.path.join("linux");
+        fs::rename(&top, &tree).map_err(|err| Error::io(Path::new(&top), err))?;
+        let _ = fs::remove_dir_all(&scratch);
+        Ok(tree)
+    }
+
+    // Copies the pristine tree of `version` to `dst`, which must not exist.
+    pub fn install(&self, version: &str, dst: &Path,
+                   progress: impl FnMut(u64, Option<u64>)) -> Result<()> {
+        if dst.exists() {
+            return Err(Error::io(dst, std::io::ErrorKind::AlreadyExists.into()));
+        }
+        let tree = self.tree(version, progress)?;
+        copy_tree(&tree, dst)
+    }
+
+    // Removes the given versions from the cache, every cached release if
+    // there is none, and returns what was removed.
+    pub fn prune(&self, versions: &[KernelVersion]) -> Result<Vec<CachedKernel>> {
+        let mut pruned = Vec::new();
+        for kernel in self.list()? {
+            if versions.is_empty() || versions.contains(&kernel.version) {
+                fs::remove_dir_all(&kernel.path)
+                    .map_err(|err| Error::io(&kernel.path, err))?;
+                pruned.push(kernel);
+            }
+        }
+        if versions.is_empty() {
+            let downloads = self.root.join(".downloads");
+            if downloads.exists() {
+                fs::remove_dir_all(&downloads)
+                    .map_err(|err| Error::io(&downloads, err))?;
+            }
+        }
+        Ok(pruned)
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use crate::error::{Error, Result};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Tristate {
+    No,
+    Module,
+    Yes,
+}
+
+impl fmt::Display for Tristate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Tristate::No => write!(f, "n"),
+            Tristate::Module => write!(f, "m"),
+            Tristate::Yes => write!(f, "y"),
+        }
+    }
+}
+
+// Value of an option as found in a .config. Bool options are represented
+// by Tristate::Yes/No: the .config alone cannot tell them apart.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ConfigValue {
+    Tristate(Tristate),
+    Int(i64),
+    Hex(u64),
+    String(String),
+}
+
+impl ConfigValue {
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "y" => Some(ConfigValue::Tristate(Tristate::Yes)),
+            "m" => Some(ConfigValue::Tristate(Tristate::Module)),
+            "n" => Some(ConfigValue::Tristate(Tristate::No)),
+            _ => {
+                if let Some(quoted) = value.strip_prefix('"') {
+                    unescape(quoted.strip_suffix('"')?)
+                        .map(ConfigValue::String)
+                }else if let Some(hex) = value.strip_prefix("0x")
+                    .or_else(|| value.strip_prefix("0X")) {
+                        u64::from_str_radix(hex, 16).ok().map(ConfigValue::Hex)
+                    }else {
+                        value.parse().ok().map(ConfigValue::Int)
+                    }
+            }
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, ConfigValue::Tristate(Tristate::No))
+    }
+}
+
+impl fmt::Display for ConfigValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigValue::Tristate(t) => write!(f, "{}", t),
+            ConfigValue::Int(i) => write!(f, "{}", i),
+            ConfigValue::Hex(h) => write!(f, "0x{:x}", h),
+            ConfigValue::String(s) => write!(f, "\"{}\"",
+                                             s.replace('\\', "\\\\")
+                                             .replace('"', "\\\"")),
+        }
+    }
+}
+
+// Kconfig only escapes '"' and '\' in string values.
+fn unescape(quoted: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.push(chars.next()?),
+            '"' => return None,
+            _ => unescaped.push(c),
+        }
+    }
+    Some(unescaped)
+}
+
+// Option names are stored without their CONFIG_ prefix, whether they come
+// from a `CONFIG_X=...` or a `# CONFIG_X is not set` line.
+pub fn normalise(name: &str) -> &str {
+    name.strip_prefix("CONFIG_").unwrap_or(name)
+}
+
+// The .config line setting `name` (without CONFIG_) to `value`.
+pub fn option_line(name: &str, value: &ConfigValue) -> String {
+    match value {
+        ConfigValue::Tristate(Tristate::No) =>
+            format!("# CONFIG_{} is not set", name),
+        _ => format!("CONFIG_{}={}", name, value),
+    }
+}
+
+
+#[derive(Debug, Clone)]
+enum Line {
+    // `raw` is the line as read, kept until the value is changed so that an
+    // untouched configuration is written back byte for byte.
+    Option { name: String, value: ConfigValue, raw: Option<String> },
+    Other(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct KernelConfig {
+    lines: Vec<Line>,
+    index: HashMap<String, usize>,
+    trailing_newline: bool,
+}
+
+impl KernelConfig {
+
+    pub fn new() -> Self {
+        Self { trailing_newline: true, ..Default::default() }
+    }
+
+    pub fn from_file(config: &Path) -> Result<Self> {
+        let content = fs::read_to_string(config)
+            .map_err(|err| Error::io(config, err))?;
+        Self::parse(&content).map_err(|err| err.in_file(config))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut configuration = Self::new();
+        let mut lines: Vec<&str> = content.split('\n').collect();
+        configuration.trailing_newline = lines.last() == Some(&"");
+        if configuration.trailing_newline {
+            lines.pop();
+        }
+
+        for (n, line) in lines.into_iter().enumerate() {
+            if line.starts_with('#') {
+                // CRLF files are written back as read, but still understood.
+                // Only `# CONFIG_X is not set`, as Kconfig reads it.
+                let not_set = line.trim_end_matches('\r').strip_prefix("# ")
+                    .and_then(|l| l.strip_suffix(" is not set"))
+                    .filter(|option| option.starts_with("CONFIG_"));
+                match not_set {
+                    Some(option) if !option.contains(' ') => configuration
+                        .push(normalise(option), ConfigValue::Tristate(Tristate::No),
+                              Some(line.to_string())),
+                    _ => configuration.lines.push(Line::Other(line.to_string())),
+                }
+            }else if line.trim().is_empty() {
+                configuration.lines.push(Line::Other(line.to_string()));
+            }else {
+                let (option, value) = line.split_once('=')
+                    .ok_or_else(|| Error::parse(
+                        Path::new(""), n + 1,
+                        format!("expected OPTION=value: {}", line)))?;
+                let value = ConfigValue::parse(value.trim_end())
+                    .ok_or_else(|| Error::parse(
+                        Path::new(""), n + 1,
+                        format!("invalid value for {}: {}", option, value)))?;
+                configuration.push(normalise(option), value,
+                                   Some(line.to_string()));
+            }
+        }
+        Ok(configuration)
+    }
+
+    fn push(&mut self, name: &str, value: ConfigValue, raw: Option<String>) {
+        self.index.insert(name.to_string(), self.lines.len());
+        self.lines.push(Line::Option { name: name.to_string(), value, raw });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ConfigValue> {
+        match self.index.get(normalise(name)).map(|&i| &self.lines[i]) {
+            Some(Line::Option { value, .. }) => Some(value),
+            _ => None,
+        }
+    }
+
+    // The value as written in the .config, e.g. 0x0010 where `get` gives
+    // 0x10, and n for `# CONFIG_X is not set`. Values set since are written
+    // as `set` writes them.
+    pub fn get_raw(&self, name: &str) -> Option<String> {
+        match self.index.get(normalise(name)).map(|&i| &self.lines[i]) {
+            Some(Line::Option { value, raw: Some(raw), .. }) => Some(
+                match raw.split_once('=').filter(|_| !raw.starts_with('#')) {
+                    Some((_, text)) => text.trim_end().to_string(),
+                    None => value.to_string(),
+                }),
+            Some(Line::Option { value, raw: None, .. }) => Some(value.to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.index.contains_key(normalise(name))
+    }
+
+    // Sets the last line of the option, dropping any earlier one.
+    pub fn set(&mut self, name: &str, value: ConfigValue) {
+        let name = normalise(name);
+        match self.index.get(name) {
+            Some(&i) => {
+                self.lines[i] = Line::Option {
+                    name: name.to_string(), value, raw: None
+                };
+                let stale = |line: &Line| matches!(
+                    line, Line::Option { name: n, .. } if n == name);
+                if self.lines[..i].iter().any(stale) {
+                    let mut j = 0;
+                    self.lines.retain(|line| {
+                        j += 1;
+                        j > i || !stale(line)
+                    });
+                    self.reindex();
+                }
+            },
+            None => self.push(name, value, None),
+        }
+    }
+
+    fn reindex(&mut self) {
+        self.index.clear();
+        for (i, line) in self.lines.iter().enumerate() {
+            if let Line::Option { name, .. } = line {
+                self.index.insert(name.clone(), i);
+            }
+        }
+    }
+
+    // Options in file order. An option listed twice is reported once, with
+    // the last value, as Kconfig would read it.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &ConfigValue)> {
+        self.lines.iter().enumerate().filter_map(move |(i, line)| match line {
+            Line::Option { name, value, .. }
+            if self.index.get(name) == Some(&i) => Some((name.as_str(), value)),
+            _ => None,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn write(&self, config: &Path) -> Result<()> {
+        fs::write(config, self.to_string()).map_err(|err| Error::io(config, err))
+    }
+}
+
+impl fmt::Display for KernelConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, line) in self.lines.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match line {
+                Line::Option { raw: Some(raw), .. } | Line::Other(raw) =>
+                    write!(f, "{}", raw)?,
+                Line::Option { name, value, raw: None } =>
+                    write!(f, "{}", option_line(name, value))?,
+            }
+        }
+        if self.trailing_newline && !self.lines.is_empty() {
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(content: &str) -> KernelConfig {
+        let config = KernelConfig::parse(content).unwrap();
+        assert_eq!(config.to_string(), content);
+        config
+    }
+
+    #[test]
+    fn crlf() {
+        let config = round_trip("# Linux\r\nCONFIG_A=y\r\n# CONFIG_B is not set\r\n\
+                                 CONFIG_S=\"x\"\r\n");
+        assert_eq!(config.get("A"), Some(&ConfigValue::Tristate(Tristate::Yes)));
+        assert_eq!(config.get("B"), Some(&ConfigValue::Tristate(Tristate::No)));
+        assert_eq!(config.get("S"), Some(&ConfigValue::String("x".to_string())));
+    }
+
+    #[test]
+    fn no_trailing_newline() {
+        let config = round_trip("CONFIG_A=y\n# CONFIG_B is not set");
+        assert_eq!(config.len(), 2);
+        round_trip("");
+        round_trip("\n");
+    }
+
+    #[test]
+    fn escaped_strings() {
+        let config = round_trip("CONFIG_CMDLINE=\"a \\\"b\\\" c\\\\d\"\n");
+        let value = ConfigValue::String("a \"b\" c\\d".to_string());
+        assert_eq!(config.get("CMDLINE"), Some(&value));
+        let mut config = KernelConfig::new();
+        config.set("CMDLINE", value);
+        assert_eq!(config.to_string(), "CONFIG_CMDLINE=\"a \\\"b\\\" c\\\\d\"\n");
+        assert!(KernelConfig::parse("CONFIG_S=\"a\"b\"\n").is_err());
+    }
+
+    #[test]
+    fn comments() {
+        let config = round_trip("\
+# Foo is not set
+#CONFIG_A is not set
+# CONFIG_B C is not set
+# CONFIG_D is not set
+");
+        assert_eq!(config.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+                   ["D"]);
+    }
+
+    #[test]
+    fn hex() {
+        let config = round_trip("CONFIG_A=0X1F\nCONFIG_B=0x0010\n");
+        assert_eq!(config.get("A"), Some(&ConfigValue::Hex(0x1f)));
+        assert_eq!(config.get("B"), Some(&ConfigValue::Hex(0x10)));
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut config = round_trip("CONFIG_A=y\nCONFIG_B=y\nCONFIG_A=m\n");
+        assert_eq!(config.len(), 2);
+        assert_eq!(config.get("A"),
+                   Some(&ConfigValue::Tristate(Tristate::Module)));
+        assert_eq!(config.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+                   ["B", "A"]);
+        config.set("A", ConfigValue::Tristate(Tristate::No));
+        assert_eq!(config.to_string(), "CONFIG_B=y\n# CONFIG_A is not set\n");
+        config.set("B", ConfigValue::Tristate(Tristate::Module));
+        assert_eq!(config.to_string(), "CONFIG_B=m\n# CONFIG_A is not set\n");
+        assert_eq!(config.get("A"), Some(&ConfigValue::Tristate(Tristate::No)));
+    }
+}
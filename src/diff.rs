@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde_json::json;
+use crate::config::{option_line, ConfigValue, KernelConfig, Tristate};
+
+
+// What a change of value means for the build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ChangeKind {
+    // n -> y or m.
+    Enabled,
+    // y or m -> n.
+    Disabled,
+    // y -> m.
+    YesToModule,
+    // m -> y.
+    ModuleToYes,
+    // Between two int or hex values.
+    Numeric,
+    String,
+    // The value changed of type (y -> "..."): the option changed of type
+    // between the two kernel versions, or the file was edited by hand.
+    Type,
+}
+
+impl ChangeKind {
+
+    pub fn classify(old: &ConfigValue, new: &ConfigValue) -> Self {
+        use ConfigValue::{Hex, Int};
+        match (old, new) {
+            (ConfigValue::Tristate(old), ConfigValue::Tristate(new)) =>
+                match (old, new) {
+                    (Tristate::No, _) => ChangeKind::Enabled,
+                    (_, Tristate::No) => ChangeKind::Disabled,
+                    (Tristate::Yes, _) => ChangeKind::YesToModule,
+                    _ => ChangeKind::ModuleToYes,
+                },
+            (Int(_) | Hex(_), Int(_) | Hex(_)) => ChangeKind::Numeric,
+            (ConfigValue::String(_), ConfigValue::String(_)) =>
+                ChangeKind::String,
+            _ => ChangeKind::Type,
+        }
+    }
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChangeKind::Enabled => write!(f, "enabled"),
+            ChangeKind::Disabled => write!(f, "disabled"),
+            ChangeKind::YesToModule => write!(f, "y->m"),
+            ChangeKind::ModuleToYes => write!(f, "m->y"),
+            ChangeKind::Numeric => write!(f, "numeric"),
+            ChangeKind::String => write!(f, "string"),
+            ChangeKind::Type => write!(f, "type"),
+        }
+    }
+}
+
+// How a ConfigDiff is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    // As the kernel's scripts/diffconfig.
+    Kernel,
+    // As diff -u on the two .config files, without context.
+    Unified,
+    Json,
+}
+
+impl Format {
+
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "kernel" => Some(Format::Kernel),
+            "unified" => Some(Format::Unified),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+// Comparison of two configurations, option names without CONFIG_, sorted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigDiff {
+    // Only in the second configuration.
+    pub added: BTreeMap<String, ConfigValue>,
+    // Only in the first one.
+    pub removed: BTreeMap<String, ConfigValue>,
+    pub unchanged: BTreeMap<String, ConfigValue>,
+    // (old, new).
+    pub changed: BTreeMap<String, (ConfigValue, ConfigValue)>,
+    // Values as written in the old and new files (0x0010 rather than 0x10),
+    // which the kernel format prints and compares.
+    pub raw: BTreeMap<String, (Option<String>, Option<String>)>,
+}
+
+impl ConfigDiff {
+
+    pub fn new(old: &KernelConfig, new: &KernelConfig) -> Self {
+        let mut diff = Self::default();
+        for (name, value) in old.iter() {
+            diff.raw.insert(name.to_string(),
+                            (old.get_raw(name), new.get_raw(name)));
+            match new.get(name) {
+                Some(v) if v == value => {
+                    diff.unchanged.insert(name.to_string(), value.clone());
+                },
+                Some(v) => {
+                    diff.changed.insert(name.to_string(),
+                                        (value.clone(), v.clone()));
+                },
+                None => {
+                    diff.removed.insert(name.to_string(), value.clone());
+                },
+            }
+        }
+        for (name, value) in new.iter() {
+            if !old.contains(name) {
+                diff.raw.insert(name.to_string(), (None, new.get_raw(name)));
+                diff.added.insert(name.to_string(), value.clone());
+            }
+        }
+        diff
+    }
+
+    // Compares behaviours rather than files: an option that is not set,
+    // set to n or absent is disabled in all three cases. Bool and tristate
+    // options absent from one side are thus compared to n, and only
+    // non-tristate options (int, hex, string) can be added or removed.
+    pub fn normalised(old: &KernelConfig, new: &KernelConfig) -> Self {
+        let mut diff = Self::new(old, new);
+        let no = ConfigValue::Tristate(Tristate::No);
+        for (name, value) in std::mem::take(&mut diff.removed) {
+            match value {
+                ConfigValue::Tristate(Tristate::No) => {
+                    diff.unchanged.insert(name, value);
+                },
+                ConfigValue::Tristate(_) => {
+                    diff.changed.insert(name, (value, no.clone()));
+                },
+                _ => {
+                    diff.removed.insert(name, value);
+                },
+            }
+        }
+        for (name, value) in std::mem::take(&mut diff.added) {
+            match value {
+                ConfigValue::Tristate(Tristate::No) => {
+                    diff.unchanged.insert(name, value);
+                },
+                ConfigValue::Tristate(_) => {
+                    diff.changed.insert(name, (no.clone(), value));
+                },
+                _ => {
+                    diff.added.insert(name, value);
+                },
+            }
+        }
+        diff
+    }
+
+    // Number of options that differ.
+    pub fn len(&self) -> usize {
+        self.added.len() + self.removed.len() + self.changed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Changed options with the kind of their change.
+    pub fn changes(&self)
+                   -> impl Iterator<Item = (&str, ChangeKind,
+                                            &ConfigValue, &ConfigValue)> {
+        self.changed.iter().map(|(name, (old, new))| {
+            (name.as_str(), ChangeKind::classify(old, new), old, new)
+        })
+    }
+
+    // Changed options of a given kind, e.g. how many went from n to y/m.
+    pub fn of_kind(&self, kind: ChangeKind) -> Vec<&str> {
+        self.changes().filter(|(_, k, _, _)| *k == kind)
+            .map(|(name, _, _, _)| name)
+            .collect()
+    }
+
+    pub fn counts(&self) -> BTreeMap<ChangeKind, usize> {
+        let mut counts = BTreeMap::new();
+        for (_, kind, _, _) in self.changes() {
+            *counts.entry(kind).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    // scripts/diffconfig output: removed options (`-NAME value`), changed
+    // ones (` NAME old -> new`) then added ones (`+NAME value`), each sorted
+    // by name, without the CONFIG_ prefix. As diffconfig, values are printed
+    // and compared as written: 0x10 -> 0x0010 is a change.
+    pub fn to_kernel(&self) -> String {
+        let raw = |name: &str, old: bool, value: &ConfigValue| self.raw.get(name)
+            .and_then(|(o, n)| if old { o.clone() } else { n.clone() })
+            .unwrap_or_else(|| value.to_string());
+        let mut changed: BTreeMap<&str, (String, String)> = self.changed.iter()
+            .map(|(name, (old, new))| (name.as_str(), (raw(name, true, old),
+                                                      raw(name, false, new))))
+            .collect();
+        changed.extend(self.unchanged.iter()
+                       .map(|(name, v)| (name.as_str(), (raw(name, true, v),
+                                                         raw(name, false, v))))
+                       .filter(|(_, (old, new))| old != new));
+        let mut out = String::new();
+        for (name, value) in &self.removed {
+            out.push_str(&format!("-{} {}\n", name, raw(name, true, value)));
+        }
+        for (name, (old, new)) in changed {
+            out.push_str(&format!(" {} {} -> {}\n", name, old, new));
+        }
+        for (name, value) in &self.added {
+            out.push_str(&format!("+{} {}\n", name, raw(name, false, value)));
+        }
+        out
+    }
+
+    // .config lines that differ, sorted by option, `-` from `old` and `+`
+    // from `new`.
+    pub fn to_unified(&self, old: &str, new: &str) -> String {
+        let mut out = format!("--- {}\n+++ {}\n", old, new);
+        let mut names: Vec<&String> = self.removed.keys()
+            .chain(self.changed.keys())
+            .chain(self.added.keys())
+            .collect();
+        names.sort();
+        for name in names {
+            if let Some((old, _)) = self.changed.get(name) {
+                out.push_str(&format!("-{}\n", option_line(name, old)));
+            }
+            if let Some(old) = self.removed.get(name) {
+                out.push_str(&format!("-{}\n", option_line(name, old)));
+            }
+            if let Some((_, new)) = self.changed.get(name) {
+                out.push_str(&format!("+{}\n", option_line(name, new)));
+            }
+            if let Some(new) = self.added.get(name) {
+                out.push_str(&format!("+{}\n", option_line(name, new)));
+            }
+        }
+        out
+    }
+
+    // Values are written as in a .config, strings quoted.
+    pub fn to_json(&self) -> String {
+        let values = |options: &BTreeMap<String, ConfigValue>| options.iter()
+            .map(|(name, value)| (name.clone(), json!(value.to_string())))
+            .collect::<serde_json::Map<_, _>>();
+        let changed = self.changes()
+            .map(|(name, kind, old, new)| (name.to_string(), json!({
+                "old": old.to_string(),
+                "new": new.to_string(),
+                "kind": kind.to_string(),
+            })))
+            .collect::<serde_json::Map<_, _>>();
+        let counts = self.counts().iter()
+            .map(|(kind, n)| (kind.to_string(), json!(n)))
+            .collect::<serde_json::Map<_, _>>();
+        let json = json!({
+            "added": values(&self.added),
+            "removed": values(&self.removed),
+            "changed": changed,
+            "unchanged": self.unchanged.len(),
+            "kinds": counts,
+        });
+        serde_json::to_string_pretty(&json).unwrap_or_default() + "\n"
+    }
+
+    pub fn format(&self, format: Format, old: &str, new: &str) -> String {
+        match format {
+            Format::Kernel => self.to_kernel(),
+            Format::Unified => self.to_unified(old, new),
+            Format::Json => self.to_json(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(old: &str, new: &str) -> ConfigDiff {
+        ConfigDiff::new(&KernelConfig::parse(old).unwrap(),
+                        &KernelConfig::parse(new).unwrap())
+    }
+
+    #[test]
+    fn kernel_raw_values() {
+        let diff = diff("\
+CONFIG_A=0x0010
+CONFIG_B=0x10
+CONFIG_C=y
+CONFIG_D=\"x\"
+CONFIG_GONE=0X1f
+", "\
+CONFIG_A=0x20
+CONFIG_B=0x0010
+# CONFIG_C is not set
+CONFIG_D=\"x\"
+CONFIG_NEW=007
+");
+        assert!(diff.unchanged.contains_key("B"));
+        assert_eq!(diff.to_kernel(), "\
+-GONE 0X1f
+ A 0x0010 -> 0x20
+ B 0x10 -> 0x0010
+ C y -> n
++NEW 007
+");
+    }
+
+    #[test]
+    fn kernel_normalised() {
+        let diff = ConfigDiff::normalised(
+            &KernelConfig::parse("CONFIG_A=m\nCONFIG_B=0x01\n").unwrap(),
+            &KernelConfig::parse("# CONFIG_B is not set\n").unwrap());
+        assert_eq!(diff.to_kernel(), " A m -> n\n B 0x01 -> n\n");
+    }
+}
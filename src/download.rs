@@ -0,0 +1,376 @@
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use reqwest::blocking::Client;
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+use crate::error::{Error, Result};
+
+
+pub const MIRROR: &str = "https://cdn.kernel.org/pub/linux/kernel";
+
+// A released kernel version: 6.1, 6.1.12, 2.6.39...
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KernelVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: Option<u32>,
+}
+
+impl KernelVersion {
+
+    pub fn parse(version: &str) -> Option<Self> {
+        let mut numbers = version.split('.').map(|n| n.parse::<u32>().ok());
+        let major = numbers.next()??;
+        let minor = numbers.next()??;
+        let patch = match numbers.next() {
+            Some(patch) => Some(patch?),
+            None => None,
+        };
+        if numbers.next().is_some() {
+            return None;
+        }
+        Some(Self { major, minor, patch })
+    }
+
+    // Directory of the mirror holding the release: v6.x, or v2.6 for the
+    // older series.
+    pub fn directory(&self) -> String {
+        if self.major < 3 {
+            format!("v{}.{}", self.major, self.minor)
+        }else {
+            format!("v{}.x", self.major)
+        }
+    }
+
+    pub fn tarball(&self) -> String {
+        format!("linux-{}.tar.gz", self)
+    }
+}
+
+impl fmt::Display for KernelVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)?;
+        if let Some(patch) = self.patch {
+            write!(f, ".{}", patch)?;
+        }
+        Ok(())
+    }
+}
+
+// A verified tarball.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Download {
+    pub version: KernelVersion,
+    pub url: String,
+    pub path: PathBuf,
+    pub sha256: String,
+}
+
+// Fetches kernel tarballs from a kernel.org mirror.
+//
+//   let download = Downloader::new().dir(Path::new("/tmp"))
+//       .fetch("6.1.12", |done, total| ...)?;
+#[derive(Debug, Clone)]
+pub struct Downloader {
+    mirror: String,
+    dir: PathBuf,
+    client: Client,
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        Self {
+            mirror: MIRROR.to_string(),
+            dir: PathBuf::from("."),
+            client: Client::new(),
+        }
+    }
+}
+
+fn sha256(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path).map_err(|err| Error::io(path, err))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let n = file.read(&mut buffer).map_err(|err| Error::io(path, err))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+impl Downloader {
+
+    // kernel.org's CDN, downloading to the current directory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Base URL laid out as kernel.org's pub/linux/kernel.
+    pub fn mirror(mut self, url: &str) -> Self {
+        self.mirror = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn dir(mut self, dir: &Path) -> Self {
+        self.dir = dir.to_path_buf();
+        self
+    }
+
+    pub fn url(&self, version: &KernelVersion, file: &str) -> String {
+        [self.mirror.as_str(), &version.directory(), file].join("/")
+    }
+
+    // Expected SHA-256 of `file`, from the sha256sums.asc of the series.
+    // The PGP signature of the list itself is not checked.
+    pub fn checksum(&self, version: &KernelVersion, file: &str)
+                    -> Result<String> {
+        let url = self.url(version, "sha256sums.asc");
+        let sums = self.client.get(&url).send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .map_err(|err| Error::download(&url, err))?;
+        sums.lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(sum), Some(name), None) if name == file
+                        && sum.len() == 64
+                        && sum.chars().all(|c| c.is_ascii_hexdigit()) =>
+                        Some(sum.to_ascii_lowercase()),
+                    _ => None,
+                }
+            })
+            .next()
+            .ok_or_else(|| Error::download(&url, format!("no checksum for {}",
+                                                         file)))
+    }
+
+    // Downloads and verifies the tarball of `version`. An interrupted
+    // download (left as <tarball>.part) is resumed when the server supports
+    // ranges. `progress` is called with the bytes written so far and the
+    // total size, when known.
+    pub fn fetch(&self, version: &str,
+                 mut progress: impl FnMut(u64, Option<u64>))
+                 -> Result<Download> {
+        let version = KernelVersion::parse(version)
+            .ok_or_else(|| Error::download(version, "not a kernel version"))?;
+        let file = version.tarball();
+        let url = self.url(&version, &file);
+        let expected = self.checksum(&version, &file)?;
+        let path = self.dir.join(&file);
+        let done = |sha256| Download { version, url: url.clone(),
+                                       path: path.clone(), sha256 };
+
+        if path.exists() && sha256(&path)? == expected {
+            return Ok(done(expected));
+        }
+        fs::create_dir_all(&self.dir).map_err(|err| Error::io(&self.dir, err))?;
+        let part = self.dir.join(format!("{}.part", file));
+        let mut offset = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+
+        let mut request = self.client.get(&url);
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset));
+        }
+        let mut response = request.send()
+            .map_err(|err| Error::download(&url, err))?;
+        match response.status() {
+            // Nothing left to fetch: the checksum will tell.
+            StatusCode::RANGE_NOT_SATISFIABLE => (),
+            StatusCode::PARTIAL_CONTENT | StatusCode::OK => {
+                if response.status() == StatusCode::OK {
+                    offset = 0;
+                }
+                let total = response.content_length().map(|n| n + offset);
+                let mut out = OpenOptions::new().create(true).write(true)
+                    .append(offset > 0).truncate(offset == 0)
+                    .open(&part)
+                    .map_err(|err| Error::io(&part, err))?;
+                let mut buffer = vec![0; 1 <<16];
+                progress(offset, total);
+                loop {
+                    let n = response.read(&mut buffer)
+                        .map_err(|err| Error::download(&url, err))?;
+                    if n == 0 {
+                        break;
+                    }
+                    out.write_all(&buffer[..n])
+                        .map_err(|err| Error::io(&part, err))?;
+                    offset += n as u64;
+                    progress(offset, total);
+                }
+            },
+            status => return Err(Error::download(&url, status)),
+        }
+
+        let actual = sha256(&part)?;
+        if actual != expected {
+            let _ = fs::remove_file(&part);
+            return Err(Error::download(&url, format!(
+                "checksum mismatch: expected {}, got {}", expected, actual)));
+        }
+        fs::rename(&part, &path).map_err(|err| Error::io(&path, err))?;
+        Ok(done(actual))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use super::*;
+    use crate::testutil::TempDir;
+
+    const TARBALL: &[u8] = b"linux-6.1 sources, not really a tarball\n";
+
+    // A mirror serving sha256sums.asc and the 6.1 tarball over HTTP, one
+    // request per connection. Records the Range header of each request for
+    // the tarball.
+    struct Mirror {
+        url: String,
+        ranges: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    impl Mirror {
+
+        fn new(sums: &str, honour_ranges: bool) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/pub", listener.local_addr().unwrap());
+            let ranges = Arc::new(Mutex::new(Vec::new()));
+            let (sums, recorded) = (sums.to_string(), ranges.clone());
+            thread::spawn(move || for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut path = String::new();
+                let mut range = None;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(request) = line.strip_prefix("GET ") {
+                        path = request.split(' ').next().unwrap().to_string();
+                    }
+                    if let Some((name, value)) = line.split_once(": ") {
+                        if name.eq_ignore_ascii_case("range") {
+                            range = Some(value.to_string());
+                        }
+                    }
+                }
+                let (status, body) = match path.as_str() {
+                    "/pub/v6.x/sha256sums.asc" =>
+                        ("200 OK", sums.as_bytes().to_vec()),
+                    "/pub/v6.x/linux-6.1.tar.gz" => {
+                        recorded.lock().unwrap().push(range.clone());
+                        let offset = range.as_deref()
+                            .and_then(|r| r.strip_prefix("bytes="))
+                            .and_then(|r| r.trim_end_matches('-').parse().ok())
+                            .filter(|_| honour_ranges);
+                        match offset {
+                            Some(offset) => ("206 Partial Content",
+                                             TARBALL[offset..].to_vec()),
+                            None => ("200 OK", TARBALL.to_vec()),
+                        }
+                    },
+                    _ => ("404 Not Found", Vec::new()),
+                };
+                let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\n\
+                                        Connection: close\r\n\r\n",
+                               status, body.len());
+                let _ = stream.write_all(&body);
+            });
+            Self { url, ranges }
+        }
+
+        fn ranges(&self) -> Vec<Option<String>> {
+            self.ranges.lock().unwrap().clone()
+        }
+    }
+
+    fn sums(sum: &str) -> String {
+        format!("-----BEGIN PGP SIGNED MESSAGE-----\n\n\
+                 {}  linux-6.0.tar.gz\n{}  linux-6.1.tar.gz\n",
+                "0".repeat(64), sum)
+    }
+
+    fn expected() -> String {
+        format!("{:x}", Sha256::digest(TARBALL))
+    }
+
+    fn fetch(mirror: &Mirror, dir: &Path) -> Result<Download> {
+        Downloader::new().mirror(&mirror.url).dir(dir).fetch("6.1", |_, _| ())
+    }
+
+    #[test]
+    fn clean() {
+        let tmp = TempDir::new();
+        let mirror = Mirror::new(&sums(&expected()), true);
+        let mut progress = Vec::new();
+        let download = Downloader::new().mirror(&mirror.url).dir(tmp.path())
+            .fetch("6.1", |done, total| progress.push((done, total)))
+            .unwrap();
+        assert_eq!(download.path, tmp.path().join("linux-6.1.tar.gz"));
+        assert_eq!(download.sha256, expected());
+        assert_eq!(fs::read(&download.path).unwrap(), TARBALL);
+        assert!(!tmp.path().join("linux-6.1.tar.gz.part").exists());
+        let size = TARBALL.len() as u64;
+        assert_eq!(progress.last(), Some(&(size, Some(size))));
+        assert_eq!(mirror.ranges(), [None]);
+        // Already there: not fetched again.
+        fetch(&mirror, tmp.path()).unwrap();
+        assert_eq!(mirror.ranges().len(), 1);
+    }
+
+    #[test]
+    fn resume() {
+        let tmp = TempDir::new();
+        tmp.write("linux-6.1.tar.gz.part",
+                  std::str::from_utf8(&TARBALL[..10]).unwrap());
+        let mirror = Mirror::new(&sums(&expected()), true);
+        let download = fetch(&mirror, tmp.path()).unwrap();
+        assert_eq!(fs::read(&download.path).unwrap(), TARBALL);
+        assert_eq!(mirror.ranges(), [Some("bytes=10-".to_string())]);
+    }
+
+    #[test]
+    fn range_ignored() {
+        let tmp = TempDir::new();
+        tmp.write("linux-6.1.tar.gz.part", "garbage...");
+        let mirror = Mirror::new(&sums(&expected()), false);
+        let download = fetch(&mirror, tmp.path()).unwrap();
+        assert_eq!(fs::read(&download.path).unwrap(), TARBALL);
+        assert_eq!(mirror.ranges(), [Some("bytes=10-".to_string())]);
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let tmp = TempDir::new();
+        let mirror = Mirror::new(&sums(&"f".repeat(64)), true);
+        let err = fetch(&mirror, tmp.path()).unwrap_err();
+        assert!(matches!(&err, Error::Download { msg, .. }
+                         if msg.starts_with("checksum mismatch")), "{}", err);
+        assert!(!tmp.path().join("linux-6.1.tar.gz.part").exists());
+        assert!(!tmp.path().join("linux-6.1.tar.gz").exists());
+    }
+
+    #[test]
+    fn missing_checksum() {
+        let tmp = TempDir::new();
+        let mirror = Mirror::new("0000  linux-6.1.tar.gz\n", true);
+        let err = fetch(&mirror, tmp.path()).unwrap_err();
+        assert!(matches!(&err, Error::Download { msg, .. }
+                         if msg == "no checksum for linux-6.1.tar.gz"),
+                "{}", err);
+        assert!(mirror.ranges().is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+
+#[derive(Debug)]
+pub enum Error {
+    Io { path: PathBuf, source: io::Error },
+    // `line` is 1-based; `file` is empty when parsing from memory.
+    Parse { file: PathBuf, line: usize, msg: String },
+    Git(git2::Error),
+    Download { url: String, msg: String },
+    Build { source: PathBuf, status: Option<i32> },
+    Manifest(String),
+    Schedule(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        Error::Io { path: path.to_path_buf(), source }
+    }
+
+    pub fn parse(file: &Path, line: usize, msg: impl Into<String>) -> Self {
+        Error::Parse { file: file.to_path_buf(), line, msg: msg.into() }
+    }
+
+    pub fn download(url: &str, msg: impl fmt::Display) -> Self {
+        Error::Download { url: url.to_string(), msg: msg.to_string() }
+    }
+
+    // Sets the file of a parse error raised on in-memory content.
+    pub fn in_file(self, path: &Path) -> Self {
+        match self {
+            Error::Parse { line, msg, .. } =>
+                Error::Parse { file: path.to_path_buf(), line, msg },
+            err => err,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { path, source } =>
+                write!(f, "{}: {}", path.display(), source),
+            Error::Parse { file, line, msg } if file.as_os_str().is_empty() =>
+                write!(f, "line {}: {}", line, msg),
+            Error::Parse { file, line, msg } =>
+                write!(f, "{}:{}: {}", file.display(), line, msg),
+            Error::Git(err) => write!(f, "git: {}", err.message()),
+            Error::Download { url, msg } => write!(f, "{}: {}", url, msg),
+            Error::Build { source, status: Some(code) } =>
+                write!(f, "build of {} failed with exit code {}",
+                       source.display(), code),
+            Error::Build { source, status: None } =>
+                write!(f, "build of {} was killed", source.display()),
+            Error::Manifest(msg) => write!(f, "invalid manifest:\n{}", msg),
+            Error::Schedule(msg) => write!(f, "schedule: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Git(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<git2::Error> for Error {
+    fn from(err: git2::Error) -> Self {
+        Error::Git(err)
+    }
+}
@@ -0,0 +1,165 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::error::{Error, Result};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    // Initial commit of the kernel sources.
+    Source,
+    // Clean build, from the sources.
+    Cb,
+    // Incremental build, from the clean build of the base configuration.
+    Ib,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Started,
+    Done,
+    // The step did not go through (git, IO...) and will be run again.
+    Failed,
+    // The mutant was rejected (see validate) and is not built.
+    Rejected,
+}
+
+// One line of the journal. A step is identified by (folder, mutant, kind):
+// the source step has empty folder and mutant, base configurations are
+// named "base".
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub folder: String,
+    pub mutant: String,
+    pub kind: Kind,
+    pub status: Status,
+    // Seconds since the epoch.
+    pub at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wall: Option<f64>,
+    // Number of options differing from the base configuration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff_size: Option<usize>,
+    // Actions the make dry run announced before an incremental build.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub predicted: Option<usize>,
+    // Targets the incremental build actually rebuilt, and how many of them
+    // the dry run missed or wrongly announced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rebuilt: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub false_positives: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub false_negatives: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Record {
+
+    pub fn new(folder: &str, mutant: &str, kind: Kind, status: Status) -> Self {
+        Self {
+            folder: folder.to_string(),
+            mutant: mutant.to_string(),
+            kind,
+            status,
+            at: SystemTime::now().duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs()).unwrap_or(0),
+            commit: None,
+            success: None,
+            wall: None,
+            diff_size: None,
+            predicted: None,
+            rebuilt: None,
+            false_positives: None,
+            false_negatives: None,
+            error: None,
+        }
+    }
+
+    fn is(&self, folder: &str, mutant: &str, kind: Kind) -> bool {
+        self.folder == folder && self.mutant == mutant && self.kind == kind
+    }
+}
+
+// Append-only JSON lines file recording the steps of a campaign, so that an
+// interrupted campaign can be resumed where it stopped.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    records: Vec<Record>,
+}
+
+impl Journal {
+
+    // Reads the journal at `path`, if any. A last line cut short by a crash
+    // is ignored; any other malformed line is an error.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut records = Vec::new();
+        if path.exists() {
+            let content = fs::read_to_string(path)
+                .map_err(|err| Error::io(path, err))?;
+            let lines: Vec<&str> = content.lines().collect();
+            for (n, line) in lines.iter().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str(line) {
+                    Ok(record) => records.push(record),
+                    Err(_) if n + 1 == lines.len() && !content.ends_with('\n') =>
+                        (),
+                    Err(err) => return Err(Error::parse(path, n + 1,
+                                                        err.to_string())),
+                }
+            }
+        }
+        Ok(Self { path: path.to_path_buf(), records })
+    }
+
+    pub fn append(&mut self, record: Record) -> Result<()> {
+        let mut line = serde_json::to_string(&record)
+            .map_err(|err| Error::parse(&self.path, self.records.len() + 1,
+                                        err.to_string()))?;
+        line.push('\n');
+        let mut file = OpenOptions::new().create(true).append(true)
+            .open(&self.path)
+            .map_err(|err| Error::io(&self.path, err))?;
+        file.write_all(line.as_bytes())
+            .and_then(|_| file.sync_data())
+            .map_err(|err| Error::io(&self.path, err))?;
+        self.records.push(record);
+        Ok(())
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    // Latest record of a step.
+    pub fn last(&self, folder: &str, mutant: &str, kind: Kind)
+                -> Option<&Record> {
+        self.records.iter().rev().find(|r| r.is(folder, mutant, kind))
+    }
+
+    // Latest record of a step, if the step is over (done or rejected).
+    pub fn finished(&self, folder: &str, mutant: &str, kind: Kind)
+                    -> Option<&Record> {
+        self.last(folder, mutant, kind)
+            .filter(|r| matches!(r.status, Status::Done | Status::Rejected))
+    }
+
+    // Whether a step was started and never finished, e.g. because of a crash.
+    pub fn interrupted(&self, folder: &str, mutant: &str, kind: Kind) -> bool {
+        self.last(folder, mutant, kind)
+            .map(|r| r.status != Status::Done && r.status != Status::Rejected)
+            .unwrap_or(false)
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use crate::analysis::Owners;
+use crate::config::normalise;
+use crate::diff::ConfigDiff;
+use crate::error::{Error, Result};
+
+
+// Goals of the top-level and arch Makefiles, relative to the top of the tree
+// (core-y += arch/x86/) rather than to the Makefile.
+const TOP_GOALS: [&str; 7] = [
+    "core", "drivers", "libs", "net", "init", "virt", "head",
+];
+
+// Lists of objects that are not built into anything (extra-y, always-y...).
+const NOT_BUILT_IN: [&str; 7] = [
+    "extra", "always", "hostprogs", "userprogs", "subdir", "targets", "clean",
+];
+
+// Directories kbuild never descends into.
+const SKIPPED: [&str; 3] = ["Documentation", "tools", "scripts"];
+
+// Which options gate which objects and directories, from the kbuild
+// Makefiles of a tree:
+//
+//   obj-$(CONFIG_EXT4_FS) += ext4/          (fs/Makefile)
+//   ext4-y := balloc.o bitmap.o ...         (fs/ext4/Makefile)
+//   ext4-$(CONFIG_EXT4_FS_POSIX_ACL) += acl.o
+//
+// Paths are relative to the tree, directories ending with '/'. Option
+// names are without CONFIG_.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KbuildIndex {
+    // Object or directory -> options it is listed under.
+    pub gates: BTreeMap<String, BTreeSet<String>>,
+    // Part of a composite object -> the composite (fs/ext4/acl.o ->
+    // fs/ext4/ext4.o).
+    pub composites: BTreeMap<String, String>,
+    // Every kbuild file read.
+    pub files: Vec<String>,
+}
+
+// `$(CONFIG_FOO)` -> Some("FOO"), `y`/`m`/`objs` -> None.
+fn gate(suffix: &str) -> Option<Option<String>> {
+    match suffix {
+        "y" | "m" | "objs" => Some(None),
+        _ => suffix.strip_prefix("$(")
+            .and_then(|s| s.strip_suffix(')'))
+            .filter(|s| s.starts_with("CONFIG_"))
+            .map(|s| Some(normalise(s).to_string())),
+    }
+}
+
+// Joins with `dir` ("" for the top), keeping a trailing '/'.
+fn join(dir: &str, path: &str) -> String {
+    let path = path.trim_start_matches("./");
+    if dir.is_empty() || path.starts_with('/') {
+        path.to_string()
+    }else {
+        format!("{}/{}", dir, path)
+    }
+}
+
+impl KbuildIndex {
+
+    // Reads the Kbuild (or else Makefile) of every directory of `srctree`.
+    // The top-level and arch/<arch> Makefiles are read even next to a Kbuild,
+    // for the top goals they list (drivers-$(CONFIG_PCI) += arch/x86/pci/).
+    pub fn scan(srctree: &Path) -> Result<Self> {
+        let mut index = Self::default();
+        index.scan_dir(srctree, "")?;
+        Ok(index)
+    }
+
+    fn scan_dir(&mut self, srctree: &Path, dir: &str) -> Result<()> {
+        let path = srctree.join(dir);
+        let top = dir.is_empty() || dir.strip_prefix("arch/")
+            .map(|arch| !arch.contains('/')).unwrap_or(false);
+        let mut files = ["Kbuild", "Makefile"].iter()
+            .map(|name| path.join(name))
+            .filter(|file| file.is_file())
+            .collect::<Vec<_>>();
+        if !top {
+            files.truncate(1);
+        }
+        for file in files {
+            let content = fs::read(&file).map_err(|err| Error::io(&file, err))?;
+            self.parse(dir, &String::from_utf8_lossy(&content));
+            self.files.push(join(dir, &file.file_name().unwrap_or_default()
+                                 .to_string_lossy()));
+        }
+        let entries = fs::read_dir(&path).map_err(|err| Error::io(&path, err))?;
+        let mut subdirs: Vec<String> = entries.flatten()
+            .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .filter(|name| !name.starts_with('.'))
+            .filter(|name| !dir.is_empty() || !SKIPPED.contains(&name.as_str()))
+            .collect();
+        subdirs.sort();
+        for name in subdirs {
+            self.scan_dir(srctree, &join(dir, &name))?;
+        }
+        Ok(())
+    }
+
+    // Reads the assignments of a kbuild file of `dir`. What is not an
+    // object list (flags, targets, conditionals...) is ignored.
+    pub fn parse(&mut self, dir: &str, content: &str) {
+        let mut logical = String::new();
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            if let Some(continued) = line.strip_suffix('\\') {
+                logical.push_str(continued);
+                logical.push(' ');
+                continue;
+            }
+            logical.push_str(line);
+            let statement = std::mem::take(&mut logical);
+            let assignment = ["+=", ":=", "="].iter()
+                .find_map(|op| statement.split_once(op));
+            let (lhs, rhs) = match assignment {
+                Some(assignment) => assignment,
+                None => continue,
+            };
+            let lhs = lhs.trim();
+            let (name, suffix) = match lhs.rsplit_once('-') {
+                Some(split) => split,
+                None => continue,
+            };
+            // Composite names may be hyphenated: dm-mod-y := dm.o ...
+            if !name.chars().all(|c| c.is_ascii_alphanumeric()
+                                 || c == '_' || c == '-')
+                || NOT_BUILT_IN.contains(&name) {
+                continue;
+            }
+            let option = match gate(suffix) {
+                Some(option) => option,
+                None => continue,
+            };
+            let base = if TOP_GOALS.contains(&name) { "" } else { dir };
+            let composite = !["obj", "lib"].contains(&name)
+                && !TOP_GOALS.contains(&name);
+            for value in rhs.split_whitespace()
+                .filter(|v| !v.contains('$'))
+                .filter(|v| v.ends_with(".o") || v.ends_with('/')) {
+                let path = join(base, value);
+                let gates = self.gates.entry(path.clone()).or_default();
+                if let Some(option) = &option {
+                    gates.insert(option.clone());
+                }
+                if composite {
+                    self.composites.insert(path, join(dir, &format!("{}.o", name)));
+                }
+            }
+        }
+    }
+
+    // Options gating `object` itself or, for a part of a composite object,
+    // the part. See `owners` for the enclosing composites and directories.
+    pub fn options(&self, object: &str) -> Vec<&str> {
+        self.gates.get(object)
+            .map(|options| options.iter().map(|o| o.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    // Objects and directories an option gates directly.
+    pub fn objects(&self, option: &str) -> Vec<&str> {
+        let option = normalise(option);
+        self.gates.iter()
+            .filter(|(_, options)| options.contains(option))
+            .map(|(path, _)| path.as_str())
+            .collect()
+    }
+
+    // For every option that differs, the objects and directories it gates.
+    pub fn affected(&self, diff: &ConfigDiff) -> BTreeMap<String, Vec<String>> {
+        diff.changed.keys().chain(diff.added.keys()).chain(diff.removed.keys())
+            .map(|name| (name.clone(), self.objects(name).iter()
+                         .map(|o| o.to_string()).collect::<Vec<String>>()))
+            .filter(|(_, objects)| !objects.is_empty())
+            .collect()
+    }
+}
+
+impl Owners for KbuildIndex {
+    // The object's own gates, those of the composite it is part of, then
+    // those of each enclosing directory, innermost first.
+    fn owners(&self, object: &str) -> Vec<Vec<String>> {
+        let mut levels = Vec::new();
+        let mut push = |path: &str| {
+            let options: Vec<String> = self.options(path).iter()
+                .map(|o| o.to_string()).collect();
+            if !options.is_empty() {
+                levels.push(options);
+            }
+        };
+        push(object);
+        let mut composite = object;
+        while let Some(parent) = self.composites.get(composite) {
+            push(parent);
+            composite = parent;
+        }
+        let mut dir = object;
+        while let Some(slash) = dir.rfind('/') {
+            dir = &dir[..slash];
+            push(&format!("{}/", dir));
+        }
+        levels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::KernelConfig;
+    use crate::testutil::TempDir;
+
+    fn tree() -> TempDir {
+        let tmp = TempDir::new();
+        tmp.write("Makefile", "\
+core-y += fs/
+drivers-$(CONFIG_PCI) += drivers/ # PCI
+");
+        tmp.write("arch/x86/Kbuild", "obj-y += kernel/\n");
+        tmp.write("arch/x86/Makefile", "drivers-$(CONFIG_PCI) += arch/x86/pci/\n");
+        tmp.write("arch/x86/pci/Makefile", "obj-y += common.o\n");
+        tmp.write("fs/Makefile", "obj-$(CONFIG_EXT4_FS) += ext4/\n");
+        tmp.write("fs/ext4/Makefile", "\
+obj-$(CONFIG_EXT4_FS) += ext4.o
+ext4-y := balloc.o \\
+\tbitmap.o
+ext4-$(CONFIG_EXT4_FS_POSIX_ACL) += acl.o
+ccflags-y += -I$(src)
+");
+        tmp.write("drivers/md/Makefile", "\
+obj-$(CONFIG_BLK_DEV_DM) += dm-mod.o
+dm-mod-y += dm.o
+dm-mod-$(CONFIG_DM_INIT) += dm-init.o
+");
+        tmp.write("Documentation/Makefile", "obj-$(CONFIG_DOC) += doc.o\n");
+        tmp
+    }
+
+    fn set(options: &[&str]) -> BTreeSet<String> {
+        options.iter().map(|o| o.to_string()).collect()
+    }
+
+    #[test]
+    fn scan() {
+        let tmp = tree();
+        let index = KbuildIndex::scan(tmp.path()).unwrap();
+        assert_eq!(index.files, [
+            "Makefile", "arch/x86/Kbuild", "arch/x86/Makefile",
+            "arch/x86/pci/Makefile", "drivers/md/Makefile", "fs/Makefile",
+            "fs/ext4/Makefile",
+        ]);
+        assert_eq!(index.gates["drivers/"], set(&["PCI"]));
+        assert_eq!(index.gates["arch/x86/pci/"], set(&["PCI"]));
+        assert_eq!(index.gates["fs/"], set(&[]));
+        assert_eq!(index.gates["fs/ext4/"], set(&["EXT4_FS"]));
+        assert_eq!(index.gates["fs/ext4/ext4.o"], set(&["EXT4_FS"]));
+        assert_eq!(index.gates["fs/ext4/acl.o"], set(&["EXT4_FS_POSIX_ACL"]));
+        assert_eq!(index.gates["drivers/md/dm-init.o"], set(&["DM_INIT"]));
+        assert!(!index.gates.contains_key("Documentation/doc.o"));
+        assert!(!index.gates.keys().any(|path| path.contains("-I")));
+    }
+
+    #[test]
+    fn composites() {
+        let tmp = tree();
+        let index = KbuildIndex::scan(tmp.path()).unwrap();
+        assert_eq!(index.composites["fs/ext4/balloc.o"], "fs/ext4/ext4.o");
+        // Continued on the next line.
+        assert_eq!(index.composites["fs/ext4/bitmap.o"], "fs/ext4/ext4.o");
+        assert_eq!(index.composites["fs/ext4/acl.o"], "fs/ext4/ext4.o");
+        assert_eq!(index.composites["drivers/md/dm.o"], "drivers/md/dm-mod.o");
+        assert_eq!(index.composites["drivers/md/dm-init.o"],
+                   "drivers/md/dm-mod.o");
+        assert!(!index.composites.contains_key("fs/ext4/ext4.o"));
+        assert_eq!(index.owners("fs/ext4/acl.o"), vec![
+            vec!["EXT4_FS_POSIX_ACL".to_string()],
+            vec!["EXT4_FS".to_string()],
+            vec!["EXT4_FS".to_string()],
+        ]);
+    }
+
+    #[test]
+    fn affected() {
+        let tmp = tree();
+        let index = KbuildIndex::scan(tmp.path()).unwrap();
+        let old = KernelConfig::parse("\
+CONFIG_EXT4_FS=y
+CONFIG_PCI=y
+CONFIG_DM_INIT=y
+").unwrap();
+        let new = KernelConfig::parse("\
+CONFIG_EXT4_FS=m
+CONFIG_PCI=y
+CONFIG_EXT4_FS_POSIX_ACL=y
+").unwrap();
+        let affected = index.affected(&ConfigDiff::new(&old, &new));
+        assert_eq!(affected.keys().collect::<Vec<_>>(),
+                   ["DM_INIT", "EXT4_FS", "EXT4_FS_POSIX_ACL"]);
+        assert_eq!(affected["EXT4_FS"], ["fs/ext4/", "fs/ext4/ext4.o"]);
+        assert_eq!(affected["EXT4_FS_POSIX_ACL"], ["fs/ext4/acl.o"]);
+        assert_eq!(affected["DM_INIT"], ["drivers/md/dm-init.o"]);
+    }
+}
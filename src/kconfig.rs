@@ -0,0 +1,1280 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::config::{ConfigValue, KernelConfig, Tristate, normalise};
+use crate::error::{Error, Result};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolType {
+    Bool,
+    Tristate,
+    String,
+    Int,
+    Hex,
+    // Referenced or defined without any type.
+    Unknown,
+}
+
+impl fmt::Display for SymbolType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SymbolType::Bool => "bool",
+            SymbolType::Tristate => "tristate",
+            SymbolType::String => "string",
+            SymbolType::Int => "int",
+            SymbolType::Hex => "hex",
+            SymbolType::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl fmt::Display for CompareOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            CompareOp::Equal => "=",
+            CompareOp::NotEqual => "!=",
+            CompareOp::Less => "<",
+            CompareOp::LessEqual => "<=",
+            CompareOp::Greater => ">",
+            CompareOp::GreaterEqual => ">=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+// Kconfig expression. `Symbol` also holds the constants y, m, n, numbers
+// and unexpanded $(macros), told apart at evaluation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Symbol(String),
+    Literal(String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(CompareOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+
+    fn and(left: Option<Expr>, right: Expr) -> Expr {
+        match left {
+            Some(left) => Expr::And(Box::new(left), Box::new(right)),
+            None => right,
+        }
+    }
+
+    fn or(left: Option<Expr>, right: Expr) -> Expr {
+        match left {
+            Some(left) => Expr::Or(Box::new(left), Box::new(right)),
+            None => right,
+        }
+    }
+
+    // Symbols the expression refers to.
+    pub fn symbols(&self) -> Vec<&str> {
+        match self {
+            Expr::Symbol(name) => vec![name.as_str()],
+            Expr::Literal(_) => Vec::new(),
+            Expr::Not(e) => e.symbols(),
+            Expr::And(l, r) | Expr::Or(l, r) | Expr::Compare(_, l, r) => {
+                let mut symbols = l.symbols();
+                symbols.extend(r.symbols());
+                symbols
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Symbol(name) => write!(f, "{}", name),
+            Expr::Literal(s) => write!(f, "\"{}\"", s),
+            Expr::Not(e) => match **e {
+                Expr::Symbol(_) | Expr::Literal(_) => write!(f, "!{}", e),
+                _ => write!(f, "!({})", e),
+            },
+            Expr::And(l, r) => {
+                for (i, e) in [l, r].into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " && ")?;
+                    }
+                    match **e {
+                        Expr::Or(..) => write!(f, "({})", e)?,
+                        _ => write!(f, "{}", e)?,
+                    }
+                }
+                Ok(())
+            },
+            Expr::Or(l, r) => write!(f, "{} || {}", l, r),
+            Expr::Compare(op, l, r) => write!(f, "{}{}{}", l, op, r),
+        }
+    }
+}
+
+// A `prompt`, `default`, `select`, `imply` or `range` with its optional
+// `if`.
+#[derive(Debug, Clone)]
+pub struct Property<T> {
+    pub value: T,
+    pub condition: Option<Expr>,
+}
+
+// One `config`/`menuconfig` entry. A symbol may be defined several times.
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub file: PathBuf,
+    pub line: usize,
+    pub prompt: Option<Property<String>>,
+    // Own `depends on` and those of the enclosing menus, ifs and choice.
+    pub depends: Option<Expr>,
+    pub defaults: Vec<Property<Expr>>,
+    pub selects: Vec<Property<String>>,
+    pub implies: Vec<Property<String>>,
+    pub ranges: Vec<Property<(Expr, Expr)>>,
+    // Index in Kconfig::choices.
+    pub choice: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolType,
+    pub definitions: Vec<Definition>,
+    // Symbols selecting (implying) this one, with the condition.
+    pub selected_by: Vec<Property<String>>,
+    pub implied_by: Vec<Property<String>>,
+}
+
+impl Symbol {
+
+    // The symbol is visible if any of its definitions is.
+    pub fn depends(&self) -> Option<Expr> {
+        let mut depends = None;
+        for definition in &self.definitions {
+            match &definition.depends {
+                Some(d) => depends = Some(Expr::or(depends, d.clone())),
+                None => return None,
+            }
+        }
+        depends
+    }
+
+    pub fn has_prompt(&self) -> bool {
+        self.definitions.iter().any(|d| d.prompt.is_some())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Choice {
+    pub prompt: Option<Property<String>>,
+    pub depends: Option<Expr>,
+    pub members: Vec<String>,
+    pub optional: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum Issue {
+    // Set in the .config but defined in no Kconfig file.
+    Unknown { name: String },
+    TypeMismatch { name: String, expected: SymbolType, value: ConfigValue },
+    UnmetDependency { name: String, value: ConfigValue, depends: Expr },
+    OutOfRange { name: String, value: ConfigValue, min: String, max: String },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::Unknown { name } => write!(f, "{}: unknown symbol", name),
+            Issue::TypeMismatch { name, expected, value } =>
+                write!(f, "{}: {} is not a valid {} value",
+                       name, value, expected),
+            Issue::UnmetDependency { name, value, depends } =>
+                write!(f, "{}={}: unmet dependencies {}", name, value, depends),
+            Issue::OutOfRange { name, value, min, max } =>
+                write!(f, "{}={}: out of range [{}, {}]", name, value, min, max),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(&'static str),
+}
+
+fn tokenize(line: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = line.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            ' ' | '\t' | '\r' => i += 1,
+            '#' => break,
+            '"' | '\'' => {
+                let mut s = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err("unterminated string".to_string()),
+                        Some('\\') => {
+                            if let Some(&n) = chars.get(i + 1) {
+                                s.push(n);
+                            }
+                            i += 2;
+                        },
+                        Some(&q) if q == c => {
+                            i += 1;
+                            break;
+                        },
+                        Some(&o) => {
+                            s.push(o);
+                            i += 1;
+                        }
+                    }
+                }
+                tokens.push(Token::Quoted(s));
+            },
+            '(' | ')' => {
+                tokens.push(Token::Op(if c == '(' { "(" } else { ")" }));
+                i += 1;
+            },
+            '!' | '<' | '>' | '=' | '&' | '|' => {
+                let two: String = chars[i..(i + 2).min(chars.len())].iter()
+                    .collect();
+                let op = match two.as_str() {
+                    "!=" => "!=",
+                    "<=" => "<=",
+                    ">=" => ">=",
+                    "&&" => "&&",
+                    "||" => "||",
+                    _ => match c {
+                        '!' => "!",
+                        '<' => "<",
+                        '>' => ">",
+                        '=' => "=",
+                        _ => return Err(format!("unexpected '{}'", c)),
+                    }
+                };
+                i += op.len();
+                tokens.push(Token::Op(op));
+            },
+            _ => {
+                // A word, possibly holding balanced $(...) macro calls.
+                let mut s = String::new();
+                let mut depth = 0;
+                while i < chars.len() {
+                    let c = chars[i];
+                    if depth == 0 && (c.is_whitespace()
+                                      || "\"'()!<>=&|#".contains(c)) {
+                        break;
+                    }
+                    if c == '$' && chars.get(i + 1) == Some(&'(') {
+                        depth += 1;
+                        s.push_str("$(");
+                        i += 2;
+                        continue;
+                    }
+                    if c == ')' {
+                        depth -= 1;
+                    }
+                    s.push(c);
+                    i += 1;
+                }
+                tokens.push(Token::Word(s));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct ExprParser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> ExprParser<'a> {
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, op: &'static str) -> bool {
+        if self.peek() == Some(&Token::Op(op)) {
+            self.pos += 1;
+            true
+        }else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> std::result::Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.eat("||") {
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> std::result::Result<Expr, String> {
+        let mut left = self.not()?;
+        while self.eat("&&") {
+            left = Expr::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> std::result::Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.compare()
+    }
+
+    fn compare(&mut self) -> std::result::Result<Expr, String> {
+        let left = self.atom()?;
+        let op = match self.peek() {
+            Some(Token::Op("=")) => CompareOp::Equal,
+            Some(Token::Op("!=")) => CompareOp::NotEqual,
+            Some(Token::Op("<")) => CompareOp::Less,
+            Some(Token::Op("<=")) => CompareOp::LessEqual,
+            Some(Token::Op(">")) => CompareOp::Greater,
+            Some(Token::Op(">=")) => CompareOp::GreaterEqual,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.atom()?;
+        Ok(Expr::Compare(op, Box::new(left), Box::new(right)))
+    }
+
+    fn atom(&mut self) -> std::result::Result<Expr, String> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Word(w)) => Ok(Expr::Symbol(normalise(&w).to_string())),
+            Some(Token::Quoted(s)) => Ok(Expr::Literal(s)),
+            Some(Token::Op("(")) => {
+                let e = self.or()?;
+                if !self.eat(")") {
+                    return Err("missing ')'".to_string());
+                }
+                Ok(e)
+            },
+            Some(Token::Op(op)) => Err(format!("unexpected '{}'", op)),
+            None => Err("expression expected".to_string()),
+        }
+    }
+}
+
+// Parses a whole expression, or one followed by `if <expr>`.
+fn parse_expr(tokens: &[Token]) -> std::result::Result<(Expr, Option<Expr>),
+                                                         String> {
+    let mut parser = ExprParser { tokens, pos: 0 };
+    let expr = parser.or()?;
+    let condition = match parser.peek() {
+        None => None,
+        Some(Token::Word(w)) if w == "if" => {
+            let (condition, rest) = parse_expr(&tokens[parser.pos + 1..])?;
+            if rest.is_some() {
+                return Err("unexpected 'if'".to_string());
+            }
+            Some(condition)
+        },
+        Some(token) => return Err(format!("unexpected {:?}", token)),
+    };
+    Ok((expr, condition))
+}
+
+// Splits `<prompt> [if <expr>]`.
+fn parse_prompt(tokens: &[Token]) -> std::result::Result<(String, Option<Expr>),
+                                                         String> {
+    match tokens.first() {
+        Some(Token::Quoted(prompt)) => {
+            let condition = match tokens.get(1) {
+                None => None,
+                Some(Token::Word(w)) if w == "if" =>
+                    Some(parse_expr(&tokens[2..])?.0),
+                Some(token) => return Err(format!("unexpected {:?}", token)),
+            };
+            Ok((prompt.clone(), condition))
+        },
+        _ => Err("prompt expected".to_string()),
+    }
+}
+
+fn indentation(line: &str) -> usize {
+    let mut width = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => width += 1,
+            '\t' => width = (width / 8 + 1) * 8,
+            _ => break,
+        }
+    }
+    width
+}
+
+
+enum Frame {
+    Menu(Option<Expr>),
+    If(Expr),
+    Choice(usize),
+}
+
+enum Entry {
+    None,
+    Config(String, SymbolType, Box<Definition>),
+    // Menus, comments and choices only take a few attributes.
+    Menu,
+    Comment,
+    Choice(usize),
+}
+
+struct Parser<'a> {
+    srctree: &'a Path,
+    variables: HashMap<String, String>,
+    symbols: BTreeMap<String, Symbol>,
+    choices: Vec<Choice>,
+    files: Vec<PathBuf>,
+    frames: Vec<Frame>,
+    entry: Entry,
+}
+
+impl<'a> Parser<'a> {
+
+    fn expand(&self, s: &str) -> String {
+        let mut expanded = s.to_string();
+        for (name, value) in &self.variables {
+            expanded = expanded.replace(&format!("$({})", name), value);
+        }
+        expanded
+    }
+
+    // Dependencies inherited from the enclosing blocks.
+    fn inherited(&self) -> Option<Expr> {
+        let mut depends = None;
+        for frame in &self.frames {
+            let expr = match frame {
+                Frame::Menu(Some(e)) | Frame::If(e) => e.clone(),
+                Frame::Choice(i) => match &self.choices[*i].depends {
+                    Some(e) => e.clone(),
+                    None => continue,
+                },
+                Frame::Menu(None) => continue,
+            };
+            depends = Some(Expr::and(depends, expr));
+        }
+        depends
+    }
+
+    fn symbol(&mut self, name: &str) -> &mut Symbol {
+        self.symbols.entry(name.to_string()).or_insert_with(|| Symbol {
+            name: name.to_string(),
+            kind: SymbolType::Unknown,
+            definitions: Vec::new(),
+            selected_by: Vec::new(),
+            implied_by: Vec::new(),
+        })
+    }
+
+    fn finish_entry(&mut self) {
+        let entry = std::mem::replace(&mut self.entry, Entry::None);
+        if let Entry::Config(name, kind, mut definition) = entry {
+            let inherited = self.inherited();
+            definition.depends = match (inherited, definition.depends.take()) {
+                (Some(i), Some(d)) => Some(Expr::and(Some(i), d)),
+                (i, d) => i.or(d),
+            };
+            if let Some(Frame::Choice(i)) = self.frames.iter().rev()
+                .find(|f| matches!(f, Frame::Choice(_))) {
+                    definition.choice = Some(*i);
+                    if !self.choices[*i].members.contains(&name) {
+                        self.choices[*i].members.push(name.clone());
+                    }
+                }
+            for select in &definition.selects {
+                self.symbol(&select.value).selected_by.push(Property {
+                    value: name.clone(), condition: select.condition.clone()
+                });
+            }
+            for imply in &definition.implies {
+                self.symbol(&imply.value).implied_by.push(Property {
+                    value: name.clone(), condition: imply.condition.clone()
+                });
+            }
+            let symbol = self.symbol(&name);
+            if symbol.kind == SymbolType::Unknown {
+                symbol.kind = kind;
+            }
+            symbol.definitions.push(*definition);
+        }
+    }
+
+    fn parse_file(&mut self, file: &Path) -> Result<()> {
+        let content = fs::read_to_string(file)
+            .map_err(|err| Error::io(file, err))?;
+        self.files.push(file.to_path_buf());
+        let lines: Vec<&str> = content.lines().collect();
+
+        let mut n = 0;
+        while n < lines.len() {
+            let first = n;
+            let mut line = lines[n].to_string();
+            while line.ends_with('\\') && n + 1 < lines.len() {
+                line.pop();
+                n += 1;
+                line.push_str(lines[n]);
+            }
+            n += 1;
+            self.parse_line(file, first + 1, &line)
+                .map_err(|msg| Error::parse(file, first + 1, msg))?;
+
+            // Help text: every following line indented at least as much as
+            // its first line, blank lines included.
+            let keyword = line.split_whitespace().next().unwrap_or_default();
+            if keyword == "help" || keyword == "---help---" {
+                let mut help_indent = None;
+                while n < lines.len() {
+                    if lines[n].trim().is_empty() {
+                        n += 1;
+                        continue;
+                    }
+                    let indent = indentation(lines[n]);
+                    match help_indent {
+                        None if indent > indentation(&line) =>
+                            help_indent = Some(indent),
+                        Some(i) if indent >= i => (),
+                        _ => break,
+                    }
+                    n += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_line(&mut self, file: &Path, line_number: usize, line: &str)
+                  -> std::result::Result<(), String> {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            return Ok(());
+        }
+
+        // Variable assignment: NAME := value, NAME = value, NAME += value.
+        if let Some((name, op, value)) = ["+=", ":=", "="].iter()
+            .filter_map(|op| trimmed.split_once(op)
+                        .map(|(n, v)| (n.trim(), *op, v.trim())))
+            .find(|(n, _, _)| !n.is_empty() && n.chars()
+                  .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')) {
+                let value = self.expand(value);
+                match op {
+                    "+=" => self.variables.entry(name.to_string()).or_default()
+                        .push_str(&format!(" {}", value)),
+                    _ => {
+                        self.variables.insert(name.to_string(), value);
+                    }
+                }
+                return Ok(());
+            }
+
+        let tokens = tokenize(trimmed)?;
+        let (keyword, args) = match tokens.split_first() {
+            Some((Token::Word(keyword), args)) => (keyword.as_str(), args),
+            _ => return Err(format!("unexpected line: {}", trimmed)),
+        };
+        // A macro call on its own, e.g. $(error-if,...) in
+        // scripts/Kconfig.include: its side effects are not emulated.
+        if keyword.starts_with("$(") && args.is_empty() {
+            return Ok(());
+        }
+        let word = |i: usize| match args.get(i) {
+            Some(Token::Word(w)) => Ok(normalise(w).to_string()),
+            _ => Err(format!("{}: symbol expected", keyword)),
+        };
+
+        match keyword {
+            "config" | "menuconfig" => {
+                self.finish_entry();
+                self.entry = Entry::Config(word(0)?, SymbolType::Unknown,
+                                           Box::new(Definition {
+                                               file: file.to_path_buf(),
+                                               line: line_number,
+                                               prompt: None,
+                                               depends: None,
+                                               defaults: Vec::new(),
+                                               selects: Vec::new(),
+                                               implies: Vec::new(),
+                                               ranges: Vec::new(),
+                                               choice: None,
+                                           }));
+            },
+            "choice" => {
+                self.finish_entry();
+                self.choices.push(Choice {
+                    prompt: None, depends: None, members: Vec::new(),
+                    optional: false,
+                });
+                let i = self.choices.len() - 1;
+                self.frames.push(Frame::Choice(i));
+                self.entry = Entry::Choice(i);
+            },
+            "endchoice" => {
+                self.finish_entry();
+                match self.frames.pop() {
+                    Some(Frame::Choice(_)) => (),
+                    _ => return Err("endchoice without choice".to_string()),
+                }
+            },
+            "menu" => {
+                self.finish_entry();
+                self.frames.push(Frame::Menu(None));
+                self.entry = Entry::Menu;
+            },
+            "endmenu" => {
+                self.finish_entry();
+                match self.frames.pop() {
+                    Some(Frame::Menu(_)) => (),
+                    _ => return Err("endmenu without menu".to_string()),
+                }
+            },
+            "if" => {
+                self.finish_entry();
+                let (expr, _) = parse_expr(args)?;
+                self.frames.push(Frame::If(expr));
+            },
+            "endif" => {
+                self.finish_entry();
+                match self.frames.pop() {
+                    Some(Frame::If(_)) => (),
+                    _ => return Err("endif without if".to_string()),
+                }
+            },
+            "comment" => {
+                self.finish_entry();
+                self.entry = Entry::Comment;
+            },
+            "mainmenu" => self.finish_entry(),
+            "source" | "rsource" | "osource" | "orsource" => {
+                self.finish_entry();
+                let path = match args.first() {
+                    Some(Token::Quoted(p)) | Some(Token::Word(p)) => self.expand(p),
+                    _ => return Err("source: path expected".to_string()),
+                };
+                let path = if keyword.ends_with("rsource") {
+                    file.parent().unwrap_or(self.srctree).join(path)
+                }else {
+                    self.srctree.join(path)
+                };
+                if !path.is_file() && keyword.starts_with('o') {
+                    return Ok(());
+                }
+                self.parse_file(&path).map_err(|err| err.to_string())?;
+            },
+            "bool" | "tristate" | "string" | "int" | "hex"
+                | "def_bool" | "def_tristate" => {
+                    let kind = match keyword {
+                        "bool" | "def_bool" => SymbolType::Bool,
+                        "tristate" | "def_tristate" => SymbolType::Tristate,
+                        "string" => SymbolType::String,
+                        "int" => SymbolType::Int,
+                        _ => SymbolType::Hex,
+                    };
+                    if keyword.starts_with("def_") {
+                        let (expr, condition) = parse_expr(args)?;
+                        self.default(Property { value: expr, condition })?;
+                    }else if !args.is_empty() {
+                        self.prompt(parse_prompt(args)?)?;
+                    }
+                    match &mut self.entry {
+                        Entry::Config(_, k, _) => *k = kind,
+                        Entry::Choice(_) => (),
+                        _ => return Err(format!("unexpected {}", keyword)),
+                    }
+                },
+            "prompt" => self.prompt(parse_prompt(args)?)?,
+            "default" => {
+                let (expr, condition) = parse_expr(args)?;
+                self.default(Property { value: expr, condition })?;
+            },
+            "depends" => {
+                match args.first() {
+                    Some(Token::Word(on)) if on == "on" => (),
+                    _ => return Err("depends: 'on' expected".to_string()),
+                }
+                let (expr, _) = parse_expr(&args[1..])?;
+                match &mut self.entry {
+                    Entry::Config(_, _, d) =>
+                        d.depends = Some(Expr::and(d.depends.take(), expr)),
+                    Entry::Choice(i) => {
+                        let c = &mut self.choices[*i];
+                        c.depends = Some(Expr::and(c.depends.take(), expr));
+                    },
+                    Entry::Menu => match self.frames.last_mut() {
+                        Some(Frame::Menu(d)) =>
+                            *d = Some(Expr::and(d.take(), expr)),
+                        _ => return Err("misplaced depends".to_string()),
+                    },
+                    Entry::Comment => (),
+                    Entry::None => return Err("misplaced depends".to_string()),
+                }
+            },
+            "select" | "imply" => {
+                let (target, condition) = match parse_expr(args)? {
+                    (Expr::Symbol(target), condition) => (target, condition),
+                    _ => return Err(format!("{}: symbol expected", keyword)),
+                };
+                match &mut self.entry {
+                    Entry::Config(_, _, d) if keyword == "select" =>
+                        d.selects.push(Property { value: target, condition }),
+                    Entry::Config(_, _, d) =>
+                        d.implies.push(Property { value: target, condition }),
+                    _ => return Err(format!("misplaced {}", keyword)),
+                }
+            },
+            "range" => {
+                let mut parser = ExprParser { tokens: args, pos: 0 };
+                let min = parser.atom()?;
+                let max = parser.atom()?;
+                let condition = match args.get(parser.pos) {
+                    None => None,
+                    Some(Token::Word(w)) if w == "if" =>
+                        Some(parse_expr(&args[parser.pos + 1..])?.0),
+                    Some(token) => return Err(format!("unexpected {:?}", token)),
+                };
+                match &mut self.entry {
+                    Entry::Config(_, _, d) => d.ranges.push(Property {
+                        value: (min, max), condition
+                    }),
+                    _ => return Err("misplaced range".to_string()),
+                }
+            },
+            "optional" => {
+                if let Entry::Choice(i) = self.entry {
+                    self.choices[i].optional = true;
+                }
+            },
+            // Attributes without any effect on the symbol table.
+            "help" | "---help---" | "option" | "modules" | "visible"
+                | "transitional" => (),
+            _ => return Err(format!("unknown keyword {}", keyword)),
+        }
+        Ok(())
+    }
+
+    fn prompt(&mut self, (value, condition): (String, Option<Expr>))
+              -> std::result::Result<(), String> {
+        let prompt = Property { value, condition };
+        match &mut self.entry {
+            Entry::Config(_, _, d) => d.prompt = Some(prompt),
+            Entry::Choice(i) => self.choices[*i].prompt = Some(prompt),
+            Entry::Menu | Entry::Comment => (),
+            Entry::None => return Err("misplaced prompt".to_string()),
+        }
+        Ok(())
+    }
+
+    fn default(&mut self, default: Property<Expr>)
+               -> std::result::Result<(), String> {
+        match &mut self.entry {
+            Entry::Config(_, _, d) => d.defaults.push(default),
+            Entry::Choice(_) => (),
+            _ => return Err("misplaced default".to_string()),
+        }
+        Ok(())
+    }
+}
+
+
+// Directory under arch/ of ARCH, as the top-level Makefile computes it: the
+// architecture of the host when ARCH is not given.
+pub fn srcarch(arch: Option<&str>) -> &str {
+    match arch.unwrap_or(std::env::consts::ARCH) {
+        "i386" | "x86_64" | "x86" => "x86",
+        "aarch64" => "arm64",
+        "sparc32" | "sparc64" => "sparc",
+        "parisc64" => "parisc",
+        "riscv64" | "riscv32" => "riscv",
+        "powerpc64" => "powerpc",
+        "mips64" => "mips",
+        "loongarch64" => "loongarch",
+        "s390x" => "s390",
+        arch => arch,
+    }
+}
+
+// Symbol table of a kernel Kconfig tree.
+#[derive(Debug, Clone)]
+pub struct Kconfig {
+    pub symbols: BTreeMap<String, Symbol>,
+    pub choices: Vec<Choice>,
+    // Every Kconfig file read, in order.
+    pub files: Vec<PathBuf>,
+}
+
+impl Kconfig {
+
+    // Parses `srctree`/Kconfig and every file it sources. `srcarch` is used
+    // to expand $(SRCARCH) (e.g. "x86" for ARCH=x86_64).
+    pub fn parse(srctree: &Path, srcarch: &str) -> Result<Self> {
+        Self::parse_with(srctree, &[("SRCARCH", srcarch), ("ARCH", srcarch)])
+    }
+
+    // Same as `parse`, with every variable given by the caller.
+    pub fn parse_with(srctree: &Path, variables: &[(&str, &str)])
+                      -> Result<Self> {
+        let mut parser = Parser {
+            srctree,
+            variables: variables.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            symbols: BTreeMap::new(),
+            choices: Vec::new(),
+            files: Vec::new(),
+            frames: Vec::new(),
+            entry: Entry::None,
+        };
+        parser.variables.entry("srctree".to_string())
+            .or_insert_with(|| srctree.display().to_string());
+        let root = srctree.join("Kconfig");
+        parser.parse_file(&root)?;
+        parser.finish_entry();
+        if !parser.frames.is_empty() {
+            return Err(Error::parse(&root, 0, "unterminated menu, if or choice"));
+        }
+        Ok(Self {
+            symbols: parser.symbols,
+            choices: parser.choices,
+            files: parser.files,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.get(normalise(name))
+    }
+
+    // Value of a symbol in a boolean context. `m` is only m with MODULES.
+    fn tristate(&self, config: &KernelConfig, name: &str) -> Tristate {
+        match name {
+            "y" => Tristate::Yes,
+            "n" => Tristate::No,
+            "m" => match config.get("MODULES") {
+                Some(ConfigValue::Tristate(Tristate::Yes)) => Tristate::Module,
+                _ => Tristate::No,
+            },
+            _ => match config.get(name) {
+                Some(ConfigValue::Tristate(t)) => *t,
+                _ => Tristate::No,
+            }
+        }
+    }
+
+    fn string(&self, config: &KernelConfig, expr: &Expr) -> String {
+        match expr {
+            Expr::Literal(s) => s.clone(),
+            Expr::Symbol(name) => match (self.get(name), config.get(name)) {
+                (_, Some(ConfigValue::String(s))) => s.clone(),
+                (_, Some(value)) => value.to_string(),
+                (Some(s), None) if matches!(s.kind, SymbolType::Bool
+                                            | SymbolType::Tristate) =>
+                    "n".to_string(),
+                (Some(_), None) => String::new(),
+                (None, None) => name.clone(),
+            },
+            _ => self.eval(config, expr).to_string(),
+        }
+    }
+
+    // Evaluates `expr` against the values of `config`. Symbols absent from
+    // the configuration are n.
+    pub fn eval(&self, config: &KernelConfig, expr: &Expr) -> Tristate {
+        match expr {
+            Expr::Symbol(name) => self.tristate(config, name),
+            Expr::Literal(_) => Tristate::No,
+            Expr::Not(e) => match self.eval(config, e) {
+                Tristate::Yes => Tristate::No,
+                Tristate::Module => Tristate::Module,
+                Tristate::No => Tristate::Yes,
+            },
+            Expr::And(l, r) => self.eval(config, l).min(self.eval(config, r)),
+            Expr::Or(l, r) => self.eval(config, l).max(self.eval(config, r)),
+            Expr::Compare(op, l, r) => {
+                let (l, r) = (self.string(config, l), self.string(config, r));
+                let ordering = match (number(&l), number(&r)) {
+                    (Some(l), Some(r)) => l.cmp(&r),
+                    _ => l.cmp(&r),
+                };
+                let holds = match op {
+                    CompareOp::Equal => ordering.is_eq(),
+                    CompareOp::NotEqual => ordering.is_ne(),
+                    CompareOp::Less => ordering.is_lt(),
+                    CompareOp::LessEqual => ordering.is_le(),
+                    CompareOp::Greater => ordering.is_gt(),
+                    CompareOp::GreaterEqual => ordering.is_ge(),
+                };
+                if holds { Tristate::Yes } else { Tristate::No }
+            }
+        }
+    }
+
+    // Whether `name` may be enabled (or, for non-bool options, set) given
+    // the rest of `config`.
+    pub fn dependencies_met(&self, config: &KernelConfig, name: &str) -> bool {
+        match self.get(name).map(|s| s.depends()) {
+            Some(Some(depends)) => self.eval(config, &depends) != Tristate::No,
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    // Whether `name` is shown to the user given the rest of `config`: one of
+    // its definitions has a prompt, and both the definition's dependencies
+    // and the prompt's `if` are met. Options without a visible prompt are
+    // only set by defaults and selects.
+    pub fn visible(&self, config: &KernelConfig, name: &str) -> bool {
+        let met = |expr: &Option<Expr>| expr.as_ref()
+            .map(|e| self.eval(config, e) != Tristate::No)
+            .unwrap_or(true);
+        self.get(name).map(|symbol| symbol.definitions.iter().any(|d| {
+            d.prompt.as_ref()
+                .map(|prompt| met(&d.depends) && met(&prompt.condition))
+                .unwrap_or(false)
+        })).unwrap_or(false)
+    }
+
+    // Checks every option of `config` against the symbol table.
+    pub fn check(&self, config: &KernelConfig) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        for (name, value) in config.iter() {
+            let symbol = match self.get(name) {
+                Some(symbol) if !symbol.definitions.is_empty() => symbol,
+                _ => {
+                    issues.push(Issue::Unknown { name: name.to_string() });
+                    continue;
+                }
+            };
+
+            let valid = match (symbol.kind, value) {
+                (SymbolType::Bool, ConfigValue::Tristate(t)) =>
+                    *t != Tristate::Module,
+                (SymbolType::Tristate, ConfigValue::Tristate(_)) => true,
+                (SymbolType::String, ConfigValue::String(_)) => true,
+                (SymbolType::Int, ConfigValue::Int(_)) => true,
+                (SymbolType::Hex, ConfigValue::Hex(_) | ConfigValue::Int(_)) =>
+                    true,
+                (SymbolType::Unknown, _) => true,
+                _ => false,
+            };
+            if !valid {
+                issues.push(Issue::TypeMismatch {
+                    name: name.to_string(), expected: symbol.kind,
+                    value: value.clone(),
+                });
+                continue;
+            }
+
+            if !value.is_enabled() {
+                continue;
+            }
+            if let Some(depends) = symbol.depends() {
+                // Only tristate symbols are limited to m by an m dependency.
+                let limit = match (symbol.kind, self.eval(config, &depends)) {
+                    (SymbolType::Tristate, met) => met,
+                    (_, Tristate::No) => Tristate::No,
+                    _ => Tristate::Yes,
+                };
+                let wanted = match value {
+                    ConfigValue::Tristate(t) => *t,
+                    _ => Tristate::Yes,
+                };
+                if wanted > limit {
+                    issues.push(Issue::UnmetDependency {
+                        name: name.to_string(), value: value.clone(), depends,
+                    });
+                    continue;
+                }
+            }
+
+            if let Some(v) = match value {
+                ConfigValue::Int(i) => Some(*i as i128),
+                ConfigValue::Hex(h) => Some(*h as i128),
+                _ => None,
+            } {
+                let range = symbol.definitions.iter()
+                    .flat_map(|d| &d.ranges)
+                    .find(|r| r.condition.as_ref()
+                          .map(|c| self.eval(config, c) != Tristate::No)
+                          .unwrap_or(true));
+                if let Some(range) = range {
+                    let min = self.string(config, &range.value.0);
+                    let max = self.string(config, &range.value.1);
+                    if let (Some(lo), Some(hi)) = (number(&min), number(&max)) {
+                        if v < lo || v > hi {
+                            issues.push(Issue::OutOfRange {
+                                name: name.to_string(), value: value.clone(),
+                                min, max,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        issues
+    }
+}
+
+fn number(s: &str) -> Option<i128> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => i128::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn sym(name: &str) -> Box<Expr> {
+        Box::new(Expr::Symbol(name.to_string()))
+    }
+
+    fn expr(line: &str) -> (Expr, Option<Expr>) {
+        parse_expr(&tokenize(line).unwrap()).unwrap()
+    }
+
+    fn kconfig(files: &[(&str, &str)]) -> Kconfig {
+        let tmp = TempDir::new();
+        for (file, content) in files {
+            tmp.write(file, content);
+        }
+        Kconfig::parse(tmp.path(), "x86").unwrap()
+    }
+
+    fn config(content: &str) -> KernelConfig {
+        KernelConfig::parse(content).unwrap()
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(tokenize("depends on A&&!B # comment").unwrap(), [
+            Token::Word("depends".to_string()), Token::Word("on".to_string()),
+            Token::Word("A".to_string()), Token::Op("&&"), Token::Op("!"),
+            Token::Word("B".to_string()),
+        ]);
+        assert_eq!(tokenize(r#"prompt "say \"hi\"" if X!=0x10"#).unwrap(), [
+            Token::Word("prompt".to_string()),
+            Token::Quoted("say \"hi\"".to_string()),
+            Token::Word("if".to_string()), Token::Word("X".to_string()),
+            Token::Op("!="), Token::Word("0x10".to_string()),
+        ]);
+        assert_eq!(tokenize("def_bool $(cc-option,-m64 -O2)").unwrap(), [
+            Token::Word("def_bool".to_string()),
+            Token::Word("$(cc-option,-m64 -O2)".to_string()),
+        ]);
+        assert!(tokenize("prompt \"unterminated").is_err());
+    }
+
+    #[test]
+    fn precedence() {
+        // Comparisons bind tighter than !, ! than &&, && than ||.
+        let compare = Expr::Compare(CompareOp::Equal, sym("C"),
+                                    Box::new(Expr::Literal("x".to_string())));
+        let and = Expr::And(sym("B"), Box::new(Expr::Not(Box::new(compare))));
+        assert_eq!(expr("A || B && !C = \"x\"").0,
+                   Expr::Or(sym("A"), Box::new(and)));
+        assert_eq!(expr("(A || B) && CONFIG_C if D"), (
+            Expr::And(Box::new(Expr::Or(sym("A"), sym("B"))), sym("C")),
+            Some(*sym("D")),
+        ));
+        assert_eq!(expr("!A && B").0,
+                   Expr::And(Box::new(Expr::Not(sym("A"))), sym("B")));
+        assert!(parse_expr(&tokenize("(A || B").unwrap()).is_err());
+        assert!(parse_expr(&tokenize("A if B if C").unwrap()).is_err());
+    }
+
+    #[test]
+    fn help() {
+        let kconfig = kconfig(&[("Kconfig", "\
+config A
+\tbool \"a\"
+\thelp
+\t  Not a keyword:
+\t  config HIDDEN
+
+\t    depends on NOTHING
+config B
+\tbool
+\t---help---
+\t  More text.
+")]);
+        assert!(kconfig.get("A").is_some());
+        assert!(kconfig.get("B").is_some());
+        assert!(kconfig.get("HIDDEN").is_none());
+        assert!(kconfig.get("A").unwrap().definitions[0].depends.is_none());
+    }
+
+    #[test]
+    fn source() {
+        let kconfig = kconfig(&[
+            ("Kconfig", "\
+source \"arch/$(SRCARCH)/Kconfig\"
+osource \"missing/Kconfig\"
+"),
+            ("arch/x86/Kconfig", "rsource \"Kconfig.cpu\"\n"),
+            ("arch/x86/Kconfig.cpu", "config X86_CPU\n\tbool\n"),
+        ]);
+        let files: Vec<String> = kconfig.files.iter()
+            .map(|f| f.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(files, ["Kconfig", "Kconfig", "Kconfig.cpu"]);
+        let symbol = kconfig.get("CONFIG_X86_CPU").unwrap();
+        assert!(symbol.definitions[0].file.ends_with("arch/x86/Kconfig.cpu"));
+        assert_eq!(symbol.kind, SymbolType::Bool);
+    }
+
+    #[test]
+    fn macros() {
+        let kconfig = kconfig(&[
+            ("Kconfig", "\
+source \"scripts/Kconfig.include\"
+config CC_HAS_X
+\tdef_bool $(cc-option,-mx)
+"),
+            ("scripts/Kconfig.include", "\
+failure = $(if-success,$(1),n,y)
+cc-option = $(success,trap \"rm -rf .tmp_$$\" EXIT; $(CC) -Werror $(1))
+$(error-if,$(failure,command -v $(CC)),compiler '$(CC)' not found)
+$(warning,no check)
+"),
+        ]);
+        let symbol = kconfig.get("CC_HAS_X").unwrap();
+        assert_eq!(symbol.kind, SymbolType::Bool);
+        assert_eq!(kconfig.files.len(), 2);
+    }
+
+    #[test]
+    fn choice() {
+        let kconfig = kconfig(&[("Kconfig", "\
+choice
+\tprompt \"mode\"
+\tdepends on M
+config ONE
+\tbool \"one\"
+if X
+config TWO
+\tbool \"two\"
+endif
+endchoice
+config OUTSIDE
+\tbool \"outside\"
+")]);
+        assert_eq!(kconfig.choices.len(), 1);
+        assert_eq!(kconfig.choices[0].members, ["ONE", "TWO"]);
+        assert_eq!(kconfig.choices[0].prompt.as_ref().unwrap().value, "mode");
+        assert_eq!(kconfig.get("ONE").unwrap().definitions[0].choice, Some(0));
+        assert_eq!(kconfig.get("TWO").unwrap().depends(),
+                   Some(Expr::And(sym("M"), sym("X"))));
+        assert_eq!(kconfig.get("OUTSIDE").unwrap().definitions[0].choice, None);
+    }
+
+    #[test]
+    fn inherited() {
+        let kconfig = kconfig(&[("Kconfig", "\
+menu \"m\"
+\tdepends on A
+if B
+config C
+\tbool \"c\"
+\tdepends on D
+endif
+endmenu
+config E
+\tbool \"e\"
+")]);
+        assert_eq!(kconfig.get("C").unwrap().depends(), Some(Expr::And(
+            Box::new(Expr::And(sym("A"), sym("B"))), sym("D"))));
+        assert_eq!(kconfig.get("E").unwrap().depends(), None);
+        let tmp = TempDir::new();
+        tmp.write("Kconfig", "if A\n");
+        assert!(Kconfig::parse(tmp.path(), "x86").is_err());
+    }
+
+    #[test]
+    fn visible() {
+        let kconfig = kconfig(&[("Kconfig", "\
+config A
+\tbool \"a\"
+config P
+\tbool
+\tprompt \"p\" if A
+config Q
+\ttristate \"q\" if !A
+config HIDDEN
+\tbool
+\tdefault y
+")]);
+        let prompt = kconfig.get("P").unwrap().definitions[0].prompt.clone();
+        assert_eq!(prompt.unwrap().condition, Some(*sym("A")));
+        let on = config("CONFIG_A=y\n");
+        let off = config("# CONFIG_A is not set\n");
+        assert!(kconfig.visible(&on, "P"));
+        assert!(!kconfig.visible(&off, "P"));
+        assert!(!kconfig.visible(&on, "Q"));
+        assert!(kconfig.visible(&off, "Q"));
+        assert!(!kconfig.visible(&on, "HIDDEN"));
+        assert!(kconfig.dependencies_met(&off, "P"));
+    }
+
+    #[test]
+    fn check() {
+        let kconfig = kconfig(&[("Kconfig", "\
+config MODULES
+\tbool \"modules\"
+config A
+\tbool \"a\"
+config B
+\ttristate \"b\"
+\tdepends on A
+config N
+\tint \"n\"
+\trange 1 10
+")]);
+        let issues = kconfig.check(&config("\
+CONFIG_MODULES=y
+CONFIG_GHOST=y
+CONFIG_A=m
+CONFIG_N=11
+"));
+        assert!(matches!(&issues[..], [
+            Issue::Unknown { name: ghost },
+            Issue::TypeMismatch { name: a, expected: SymbolType::Bool, .. },
+            Issue::OutOfRange { name: n, min, max, .. },
+        ] if ghost == "GHOST" && a == "A" && n == "N" && min == "1"
+                && max == "10"), "{:?}", issues);
+        let issues = kconfig.check(&config("\
+CONFIG_MODULES=y
+# CONFIG_A is not set
+CONFIG_B=y
+CONFIG_N=10
+"));
+        assert!(matches!(&issues[..], [
+            Issue::UnmetDependency { name, depends, .. },
+        ] if name == "B" && *depends == *sym("A")), "{:?}", issues);
+        assert!(kconfig.check(&config("CONFIG_A=y\nCONFIG_B=m\n")).is_empty());
+    }
+}
@@ -1,173 +1,150 @@
-use std::collections::HashMap;
 use std::fs;
-use std::io::{BufRead, BufReader, Write};
-use std::path::Path;
-use std::process::Command;
-use git2::{IndexAddOption, Repository, Oid, Config};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use git2::{BranchType, IndexAddOption, Repository, Oid, Config};
+use git2::{ObjectType, TreeWalkMode, TreeWalkResult};
+use git2::{WorktreeAddOptions, WorktreePruneOptions};
+use bzip2::read::BzDecoder;
 use flate2::read::GzDecoder;
-use tar::Archive;
-
-
-pub fn mkf_ni_trace(trace: &Path) -> HashMap<String, HashMap<String, String>> {
-
-    let file = match fs::File::open(trace) {
-        Ok(file) => file,
-        Err(err) => panic!("Error: {:?} {}", trace, err),
-    };
-
-    let mut tasks: HashMap<String, HashMap<String, String>> = HashMap::new();
-
-    for line in BufReader::new(file).lines()
-        .filter(|l| l.is_ok()).map(|l| l.unwrap()) {
-            if line.starts_with("set -e;  echo '  ") {
-                let echoed = line.strip_prefix("set -e;  echo '  ")
-                    .unwrap().trim();
-                let mut splitted = echoed[..echoed.find('\'').unwrap()]
-                    .split_whitespace();
-                let rule = splitted.nth(0).unwrap().to_string();
-                let target = splitted.nth(0).unwrap().to_string();
-                let cmd = echoed[echoed.find(';').unwrap()+1..]
-                    .trim().to_string();
-
-                if !tasks.contains_key(&rule) {
-                    tasks.insert(String::from(&rule), HashMap::new());
-                }
-                if let Some(table) = tasks.get_mut(&rule) {
-                    table.insert(String::from(&target), String::from(&cmd));
-                }
-            }
-        }
-
-    tasks
+use tar::{Archive, EntryType};
+use xz2::read::XzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+pub mod analysis;
+pub mod build;
+pub mod cache;
+pub mod config;
+pub mod diff;
+pub mod download;
+pub mod error;
+pub mod journal;
+pub mod kbuild;
+pub mod kconfig;
+pub mod manifest;
+pub mod mutate;
+pub mod results;
+pub mod schedule;
+pub mod source;
+pub mod trace;
+pub mod validate;
+#[cfg(test)]
+pub(crate) mod testutil;
+
+pub use build::{build, BuildOptions, BuildResult};
+use config::KernelConfig;
+pub use diff::{ChangeKind, ConfigDiff};
+pub use download::{Download, Downloader};
+pub use error::{Error, Result};
+pub use trace::{CommandLine, MakeAction, MakeTrace, Verbosity};
+
+
+pub fn mkf_ni_trace(trace: &Path) -> Result<MakeTrace> {
+    MakeTrace::from_file(trace)
 }
 
-pub fn mkf_ni_trace_total(table: HashMap<String, HashMap<String, String>>)
-                          -> usize {
-    let mut total = 0;
-    for (_, v) in &table {
-        total += v.len();
-    }
-    total
+pub fn mkf_ni_trace_total(trace: &MakeTrace) -> usize {
+    trace.len()
 }
 
-pub fn readconfig(config: &Path) -> HashMap<String, String> {
-
-    let file = match fs::File::open(config) {
-        Ok(file) => file,
-        Err(err) => panic!("Error: {:?} {}", config, err),
-    };
-
-    let mut configuration = HashMap::new();
-
-    for line in BufReader::new(file).lines()
-        .filter(|l| l.is_ok()).map(|l| l.unwrap()) {
-            if line.starts_with('#') {
-                if line.ends_with("is not set") {
-                    let option = line
-                        .strip_prefix("# CONFIG_").unwrap()
-                        .strip_suffix(" is not set").unwrap();
-                    configuration.insert(option.to_string(), "n".to_string());
-                }
-            }else {
-                if !line.is_empty() {
-                    let (option, value) = line.split_once('=').unwrap();
-                    configuration.insert(option.to_string(), value.to_string());
-                }
-            }
-        }
-    configuration
+pub fn readconfig(config: &Path) -> Result<KernelConfig> {
+    KernelConfig::from_file(config)
 }
 
-pub fn diffconfig(config1: &Path, config2: &Path)
-              -> HashMap<String, HashMap<String, String>> {
-
-    let c1 = readconfig(config1);
-    let c2 = readconfig(config2);
-
-    let mut
-        comparison: HashMap<String, HashMap<String, String>> = HashMap::new();
-    comparison.insert("=".to_string(), HashMap::new());
-    comparison.insert("+".to_string(), HashMap::new());
-    comparison.insert("-".to_string(), HashMap::new());
-    comparison.insert("~".to_string(), HashMap::new());
-
-    for (k, v) in c1.iter() {
-        if c2.contains_key(k) {
-            if c1.get(k) == c2.get(k) {
-                comparison.get_mut("=").unwrap()
-                    .insert(k.to_string(), v.to_string());
-            }else {
-                comparison.get_mut("~").unwrap()
-                    .insert(k.to_string(),
-                            format!("{} -> {}", c1.get(k).unwrap().to_string(),
-                                    c2.get(k).unwrap().to_string()));
-            }
-        }else {
-            comparison.get_mut("-").unwrap()
-                .insert(k.to_string(), v.to_string());
-        }
-    }
-    for (k, v) in c2.iter() {
-        if !c1.contains_key(k) {
-            comparison.get_mut("+").unwrap()
-                .insert(k.to_string(), v.to_string());
-        }
-    }
-    comparison
+pub fn diffconfig(config1: &Path, config2: &Path) -> Result<ConfigDiff> {
+    let c1 = readconfig(config1)?;
+    let c2 = readconfig(config2)?;
+    Ok(ConfigDiff::new(&c1, &c2))
 }
 
-
-pub fn build(source: &str) -> Result<(), ()>{
-    let output = Command::new("/usr/bin/time")
-        .args(["-p", "-o", "t+time", "--format=%e", "make", "-j16"])
-        .current_dir(source)
-        .output()
-        .expect("/usr/bin/time: failed to execute build process.");
-
-    let _ = fs::File::create([source, "t+build"].join("/"))
-        .unwrap().write_all(&output.stdout);
-
-    if !output.status.success() {
-        let _ = fs::File::create([source, "t+error"].join("/")).unwrap()
-            .write_all(&output.stderr);
-        return Err(());
-    }
-    Ok(())
+// Same as diffconfig, "is not set", absent and =n being the same value.
+pub fn diffconfig_normalised(config1: &Path, config2: &Path)
+                             -> Result<ConfigDiff> {
+    let c1 = readconfig(config1)?;
+    let c2 = readconfig(config2)?;
+    Ok(ConfigDiff::normalised(&c1, &c2))
 }
 
 
-pub fn makeni_trace(source: &str){
-    let output = Command::new("make")
+// Runs `make -n -i` on the configured tree and keeps its output in `trace`,
+// out of the tree so that it is not committed with the build.
+pub fn makeni_trace(source: &str, options: &BuildOptions, verbosity: Verbosity,
+                    trace: &Path) -> Result<MakeTrace> {
+    let mut make = options.make(source);
+    match verbosity {
+        Verbosity::Quiet => (),
+        Verbosity::V1 => {
+            make.arg("V=1");
+        },
+        Verbosity::KbuildVerbose => {
+            make.env("KBUILD_VERBOSE", "1");
+        },
+    }
+    let output = make
         .args(["-n", "-i"])
-        .current_dir(source)
+        .args(options.get_targets())
         .output()
-        .expect("make -ni: failed to execute build process.");
+        .map_err(|err| Error::io(Path::new("make"), err))?;
 
-    let _ = fs::File::create([source, "t+makeni"].join("/"))
-        .unwrap().write_all(&output.stdout);
+    fs::write(trace, &output.stdout).map_err(|err| Error::io(trace, err))?;
+    Ok(MakeTrace::parse(&String::from_utf8_lossy(&output.stdout)))
 }
 
 
-pub fn kernel_download(version: &str) -> Result<String, ()> {
+// Downloads the tarball of `version` from kernel.org to the current
+// directory. See download::Downloader for mirrors and progress.
+pub fn kernel_download(version: &str) -> Result<Download> {
+    Downloader::new().fetch(version, |_, _| ())
+}
 
-    let url = ["https://cdn.kernel.org/pub/linux/kernel/v",
-               &version[..version.find('.').unwrap()],
-               ".x/linux-", version, ".tar.gz"].join("");
+// Unpacks `file` into `dst` and returns the top-level directory of the
+// archive (`dst` itself when there is none). The compression (gzip, xz,
+// bzip2, zstd or none) is detected from the first bytes of the file.
+pub fn extract_tar(file: &str, dst: &str) -> Result<String> {
+    let path = Path::new(file);
+    let tarball = fs::File::open(path).map_err(|err| Error::io(path, err))?;
+    let mut tarball = BufReader::new(tarball);
+    let magic = tarball.fill_buf().map_err(|err| Error::io(path, err))?;
+    let reader: Box<dyn Read> = if magic.starts_with(&[0x1f, 0x8b]) {
+        Box::new(GzDecoder::new(tarball))
+    }else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Box::new(XzDecoder::new(tarball))
+    }else if magic.starts_with(b"BZh") {
+        Box::new(BzDecoder::new(tarball))
+    }else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Box::new(ZstdDecoder::with_buffer(tarball)
+                 .map_err(|err| Error::io(path, err))?)
+    }else {
+        Box::new(tarball)
+    };
 
-    if !Command::new("wget").arg(&url).status().unwrap().success() {
-        return Err(());
+    fs::create_dir_all(dst).map_err(|err| Error::io(Path::new(dst), err))?;
+    let mut archive = Archive::new(reader);
+    let mut top: Option<PathBuf> = None;
+    let mut single = true;
+    let entries = archive.entries().map_err(|err| Error::io(path, err))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|err| Error::io(path, err))?;
+        // git archive's pax_global_header holds the commit id, not a file.
+        if entry.header().entry_type() == EntryType::XGlobalHeader {
+            continue;
+        }
+        let name = entry.path().map_err(|err| Error::io(path, err))?;
+        if let Some(Component::Normal(first)) = name.components().next() {
+            match &top {
+                None => top = Some(PathBuf::from(first)),
+                Some(top) if top.as_os_str() != first => single = false,
+                Some(_) => (),
+            }
+        }
+        entry.unpack_in(dst).map_err(|err| Error::io(Path::new(dst), err))?;
     }
 
-    Ok(url[url.rfind('/').unwrap()+1..].to_string())
-}
-
-pub fn extract_tar(file: &str, dst: &str) -> Result<String, std::io::Error>{
-    Archive::new(GzDecoder::new(fs::File::open(file)?)).unpack(dst)?;
-    let mut sep = String::new();
-    if dst.chars().last().unwrap() != '/' {
-        sep.push_str("/");
+    match top {
+        Some(top) if single =>
+            Ok(Path::new(dst).join(top).to_string_lossy().into_owned()),
+        _ => Ok(dst.to_string()),
     }
-    Ok([dst, file.strip_suffix(".tar.gz").unwrap()].join(&sep))
 }
 
 
@@ -177,17 +154,20 @@ pub struct MyGit {
 
 impl MyGit {
 
-    pub fn new(folder: &str) -> Self {
-        Self {repo: Repository::init(folder).unwrap()}
+    pub fn new(folder: &str) -> Result<Self> {
+        Ok(Self {repo: Repository::init(folder)?})
+    }
+
+    // An existing repository, or a worktree of one.
+    pub fn open(folder: &str) -> Result<Self> {
+        Ok(Self {repo: Repository::open(folder)?})
     }
 
     pub fn config(&self, user_name: &str, user_email: &str)
-                  -> Result <Config, git2::Error>{
+                  -> Result<Config> {
 
-        let mut conf = Config::new().unwrap();
-        let path_str = [&self.repo.path().to_str().unwrap(),
-                        "config"].join("/");
-        conf.add_file(Path::new(&path_str),
+        let mut conf = Config::new()?;
+        conf.add_file(&self.repo.path().join("config"),
                       git2::ConfigLevel::Local,
                       true)?;
         conf.set_str("user.name", user_name)?;
@@ -195,77 +175,301 @@ impl MyGit {
         Ok(conf)
     }
 
-    pub fn add_all(&self) -> Result<Oid, git2::Error>{
+    pub fn add_all(&self) -> Result<Oid> {
 
         let cb = &mut |path: &Path, _matched_spec: &[u8]| -> i32 {
-            let status = self.repo.status_file(path).unwrap();
-            if status.contains(git2::Status::WT_MODIFIED)
-                || status.contains(git2::Status::WT_NEW){
-                    0
-                }else {
-                    1
-                }
+            match self.repo.status_file(path) {
+                Ok(status) if status.contains(git2::Status::WT_MODIFIED)
+                    || status.contains(git2::Status::WT_NEW) => 0,
+                _ => 1,
+            }
         };
 
-        let mut index = self.repo.index()
-            .expect("MyGit: cannot get the Index file.");
+        let mut index = self.repo.index()?;
         index.add_all(["*"].iter(),
                       IndexAddOption::DEFAULT,
-                      Some(cb as &mut git2::IndexMatchedPath))
-            .expect("MyGit: failed to add -f *");
-        index.write().expect("MyGit: failed to write modification.");
-        index.write_tree()
+                      Some(cb as &mut git2::IndexMatchedPath))?;
+        index.write()?;
+        Ok(index.write_tree()?)
     }
 
-    pub fn commit(&self, msg: &str, tree_id: Oid) -> Result<Oid, git2::Error>  {
-        let signature = self.repo.signature().unwrap();
-        let tree = self.repo.find_tree(tree_id).unwrap();
+    pub fn commit(&self, msg: &str, tree_id: Oid) -> Result<Oid> {
+        let signature = self.repo.signature()?;
+        let tree = self.repo.find_tree(tree_id)?;
         let head = self.repo.head();
-        let ret: Result<Oid, git2::Error>;
-        match head {
+        let oid = match head {
             Ok(h) => {
-                ret = self.repo.commit(Some("HEAD"),
+                self.repo.commit(Some("HEAD"),
                                         &signature,
                                         &signature,
                                         msg,
                                         &tree,
-                                        &[&h.peel_to_commit().unwrap()]
-                );
+                                        &[&h.peel_to_commit()?]
+                )
             }
             Err(_)   => {
-                ret = self.repo.commit(Some("HEAD"),
+                self.repo.commit(Some("HEAD"),
                                         &signature,
                                         &signature,
                                         msg,
                                         &tree,
                                         &[]
-                );
+                )
+            }
+        }?;
+        Ok(oid)
+    }
+
+    pub fn create_branch(&self, branch_name: &str, src_commit: Oid)
+                         -> Result<()> {
+        let srccommit = self.repo.find_commit(src_commit)?;
+        self.repo.branch(branch_name, &srccommit, false)?;
+        Ok(())
+    }
+
+    // Points `branch_name` at `src_commit`, whether the branch exists or not.
+    // HEAD is detached first when it is on that branch.
+    pub fn reset_branch(&self, branch_name: &str, src_commit: Oid)
+                        -> Result<()> {
+        let srccommit = self.repo.find_commit(src_commit)?;
+        if let Ok(head) = self.repo.head() {
+            if head.is_branch() && head.shorthand() == Some(branch_name) {
+                self.repo.set_head_detached(head.peel_to_commit()?.id())?;
             }
         }
-        ret
+        self.repo.branch(branch_name, &srccommit, true)?;
+        Ok(())
+    }
+
+    pub fn checkout(&self, branch: &str) -> Result<()> {
+        self.checkout_with(branch, None)
     }
 
-    pub fn create_branch(&self, branch_name: &str, src_commit: Oid) {
-        let srccommit = self.repo.find_commit(src_commit).unwrap();
-        let _ = self.repo.branch(branch_name, &srccommit, false);
+    // Same as checkout, but local changes and untracked files (e.g. left by
+    // an interrupted build) are discarded.
+    pub fn force_checkout(&self, branch: &str) -> Result<()> {
+        let mut builder = git2::build::CheckoutBuilder::new();
+        builder.force().remove_untracked(true);
+        self.checkout_with(branch, Some(&mut builder))
     }
 
-    pub fn checkout(&self, branch: &str) {
-        let (object, reference) = self.repo.revparse_ext(branch).expect("Object not found");
+    fn checkout_with(&self, branch: &str,
+                     builder: Option<&mut git2::build::CheckoutBuilder>)
+                     -> Result<()> {
+        let (object, reference) = self.repo.revparse_ext(branch)?;
 
-        self.repo.checkout_tree(&object, None)
-            .expect("Failed to checkout");
+        self.repo.checkout_tree(&object, builder)?;
 
         match reference {
             // gref is an actual reference like branches or tags
-            Some(gref) => self.repo.set_head(gref.name().unwrap()),
+            Some(gref) => match gref.name() {
+                Some(name) => self.repo.set_head(name),
+                None => Err(git2::Error::from_str(
+                    "reference name is not valid UTF-8")),
+            },
             // this is a commit, not a reference
             None => self.repo.set_head_detached(object.id()),
+        }?;
+        Ok(())
+    }
+
+    // Checks `branch` out in a new worktree of the repository, named `name`
+    // and at `path`. The branch must not be checked out anywhere else.
+    pub fn add_worktree(&self, name: &str, path: &Path, branch: &str)
+                        -> Result<MyGit> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| Error::io(parent, err))?;
+        }
+        let reference = self.repo.find_branch(branch, BranchType::Local)?
+            .into_reference();
+        let mut options = WorktreeAddOptions::new();
+        options.reference(Some(&reference));
+        let worktree = self.repo.worktree(name, path, Some(&options))?;
+        Ok(Self {repo: Repository::open_from_worktree(&worktree)?})
+    }
+
+    // Names and paths of the worktrees, the main working directory aside.
+    pub fn worktrees(&self) -> Result<Vec<(String, PathBuf)>> {
+        let mut worktrees = Vec::new();
+        for name in self.repo.worktrees()?.iter().flatten() {
+            let worktree = self.repo.find_worktree(name)?;
+            worktrees.push((name.to_string(), worktree.path().to_path_buf()));
+        }
+        Ok(worktrees)
+    }
+
+    // Deletes the worktree `name` and its working directory. Its branch and
+    // commits are kept.
+    pub fn remove_worktree(&self, name: &str) -> Result<()> {
+        let worktree = self.repo.find_worktree(name)?;
+        let mut options = WorktreePruneOptions::new();
+        options.valid(true).locked(true).working_tree(true);
+        worktree.prune(Some(&mut options))?;
+        Ok(())
+    }
+
+    // Forgets the worktrees whose working directory was deleted, and returns
+    // their names.
+    pub fn prune_worktrees(&self) -> Result<Vec<String>> {
+        let mut pruned = Vec::new();
+        for (name, _) in self.worktrees()? {
+            let worktree = self.repo.find_worktree(&name)?;
+            if worktree.validate().is_err() {
+                worktree.prune(None)?;
+                pruned.push(name);
+            }
+        }
+        Ok(pruned)
+    }
+
+    // Sets the modification time of the checked out files: `old` for those
+    // identical in the tree of the commit `sources`, `new` for the others
+    // (build artifacts). A fresh checkout writes files in path order, and
+    // make would take most objects for older than the headers they depend
+    // on. Symbolic links are left alone.
+    pub fn set_mtimes(&self, sources: Oid, old: SystemTime, new: SystemTime)
+                      -> Result<()> {
+        let workdir = PathBuf::from(self.get_workdir()?);
+        let sources = self.repo.find_commit(sources)?.tree()?;
+        let head = self.repo.head()?.peel_to_tree()?;
+        let mut files = Vec::new();
+        head.walk(TreeWalkMode::PreOrder, |dir, entry| {
+            if let (Some(ObjectType::Blob), Some(name)) = (entry.kind(),
+                                                           entry.name()) {
+                if entry.filemode() != 0o120000 {
+                    files.push((format!("{}{}", dir, name), entry.id()));
+                }
+            }
+            TreeWalkResult::Ok
+        })?;
+        for (path, id) in files {
+            let source = sources.get_path(Path::new(&path))
+                .map(|entry| entry.id() == id)
+                .unwrap_or(false);
+            let file = workdir.join(&path);
+            fs::File::open(&file)
+                .and_then(|f| f.set_modified(if source { old } else { new }))
+                .map_err(|err| Error::io(&file, err))?;
+        }
+        Ok(())
+    }
+
+    pub fn get_workdir(&self) -> Result<&str> {
+        self.repo.workdir().and_then(|w| w.to_str())
+            .ok_or_else(|| Error::Git(git2::Error::from_str(
+                "repository has no (UTF-8) working directory")))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::process::Command;
+    use std::time::Duration;
+    use tar::{Builder, Header};
+    use super::*;
+    use crate::testutil::TempDir;
+
+    // A tar of `files`, preceded by a pax global header as git archive
+    // writes.
+    fn tar(files: &[&str], pax: bool) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        let mut append = |path: &str, kind: EntryType, data: &[u8]| {
+            let mut header = Header::new_ustar();
+            header.set_entry_type(kind);
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, data).unwrap();
+        };
+        if pax {
+            append("pax_global_header", EntryType::XGlobalHeader,
+                   b"52 comment=0123456789abcdef0123456789abcdef01234567\n");
+        }
+        for file in files {
+            append(file, EntryType::Regular, file.as_bytes());
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn compress(tar: &[u8], kind: &str) -> Vec<u8> {
+        match kind {
+            "gz" => {
+                let mut encoder = flate2::write::GzEncoder::new(
+                    Vec::new(), flate2::Compression::default());
+                encoder.write_all(tar).unwrap();
+                encoder.finish().unwrap()
+            },
+            "xz" => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(tar).unwrap();
+                encoder.finish().unwrap()
+            },
+            "bz2" => {
+                let mut encoder = bzip2::write::BzEncoder::new(
+                    Vec::new(), bzip2::Compression::default());
+                encoder.write_all(tar).unwrap();
+                encoder.finish().unwrap()
+            },
+            "zst" => zstd::encode_all(tar, 0).unwrap(),
+            _ => tar.to_vec(),
+        }
+    }
+
+    #[test]
+    fn extract() {
+        let tmp = TempDir::new();
+        let files = ["linux-6.1/Makefile", "linux-6.1/fs/Makefile"];
+        for kind in ["gz", "xz", "bz2", "zst", "tar"] {
+            for pax in [false, true] {
+                let tarball = tmp.path().join(format!("linux-{}.{}", pax, kind));
+                fs::write(&tarball, compress(&tar(&files, pax), kind)).unwrap();
+                let dst = tmp.path().join(format!("{}-{}", kind, pax));
+                let dst = dst.to_str().unwrap();
+                let top = extract_tar(tarball.to_str().unwrap(), dst).unwrap();
+                assert_eq!(top, format!("{}/linux-6.1", dst), "{}", kind);
+                assert_eq!(fs::read_to_string(format!("{}/fs/Makefile", top))
+                           .unwrap(), "linux-6.1/fs/Makefile");
+                assert!(!Path::new(dst).join("pax_global_header").exists());
+            }
         }
-        .expect("Failed to set HEAD");
+        // Several top-level entries: `dst` itself.
+        let tarball = tmp.write("flat.tar", "");
+        fs::write(&tarball, tar(&["a/Makefile", "b"], true)).unwrap();
+        let dst = tmp.path().join("flat");
+        let dst = dst.to_str().unwrap();
+        assert_eq!(extract_tar(tarball.to_str().unwrap(), dst).unwrap(), dst);
+        assert!(Path::new(dst).join("b").is_file());
     }
 
-    pub fn get_workdir(&self) -> &str {
-        &self.repo.workdir().unwrap().to_str().unwrap()
+    // A clean build committed after the sources, checked out in a worktree
+    // and rebuilt as is: make must find everything up to date.
+    #[test]
+    fn noop_incremental_build_in_worktree() {
+        let tmp = TempDir::new();
+        tmp.write("repo/Makefile", "a.o: a.c include/a.h\n\tcp a.c a.o\n");
+        tmp.write("repo/a.c", "int a;\n");
+        tmp.write("repo/include/a.h", "extern int a;\n");
+        let repo = tmp.path().join("repo");
+        let git = MyGit::new(repo.to_str().unwrap()).unwrap();
+        git.config("Tux", "None").unwrap();
+        let tree = git.add_all().unwrap();
+        let sources = git.commit("source", tree).unwrap();
+        tmp.write("repo/a.o", "int a;\n");
+        let tree = git.add_all().unwrap();
+        let built = git.commit("clean build", tree).unwrap();
+        git.create_branch("ib", built).unwrap();
+
+        let path = tmp.path().join("worktrees/ib");
+        let worktree = git.add_worktree("ib", &path, "ib").unwrap();
+        let now = SystemTime::now();
+        worktree.set_mtimes(sources, now - Duration::from_secs(3600), now)
+            .unwrap();
+        let status = Command::new("make").args(["-q", "a.o"])
+            .current_dir(&path).status().unwrap();
+        assert!(status.success(), "a.o would be rebuilt");
+        assert_eq!(git.worktrees().unwrap(), vec![("ib".to_string(), path)]);
+        git.remove_worktree("ib").unwrap();
+        assert!(git.worktrees().unwrap().is_empty());
     }
 }
@@ -1,267 +1,724 @@
-use lmutib;
+use std::cell::RefCell;
+use std::env;
 use std::fs::{self};
 use std::io::{self, Write};
 use std::path::Path;
+use std::process;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+use git2::Oid;
+use lmutib::{BuildOptions, BuildResult, Downloader, Error, MyGit, Result};
+use lmutib::analysis::{Attribution, Breakdown, KconfigDirs};
+use lmutib::kbuild::KbuildIndex;
+use lmutib::cache::Cache;
+use lmutib::diff::Format;
+use lmutib::download::KernelVersion;
+use lmutib::journal::{Journal, Kind, Record, Status};
+use lmutib::kconfig::{srcarch, Kconfig};
+use lmutib::manifest::{Folder, Manifest, Mutant, RevertPolicy, Source};
+use lmutib::schedule::{Schedule, State};
+use lmutib::source::GitSource;
+use lmutib::trace::{MakeTrace, Prediction};
+use lmutib::{results, validate};
 
-fn main() {
 
-    let kernel = "/home/linux-5.13";
-    println!("┌───────────────────────────┐");
+thread_local! {
+    // Output of a worker building mutants in parallel, printed at once when
+    // the mutant is over rather than interleaved with the other workers'.
+    static BUFFER: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+fn emit(text: String) {
+    BUFFER.with(|buffer| match buffer.borrow_mut().as_mut() {
+        Some(buffer) => buffer.push_str(&text),
+        None => print!("{}", text),
+    });
+}
+
+macro_rules! out {
+    ($($arg:tt)*) => { emit(format!($($arg)*)) };
+}
+
+macro_rules! outln {
+    ($($arg:tt)*) => { emit(format!($($arg)*) + "\n") };
+}
+
+// Runs `f` with its output buffered, then prints it.
+fn buffered<T>(f: impl FnOnce() -> T) -> T {
+    BUFFER.with(|buffer| *buffer.borrow_mut() = Some(String::new()));
+    let ret = f();
+    let text = BUFFER.with(|buffer| buffer.borrow_mut().take())
+        .unwrap_or_default();
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(text.as_bytes()).unwrap();
+    stdout.flush().unwrap();
+    ret
+}
+
+fn flush() {
     io::stdout().flush().unwrap();
+}
+
+// Prints `msg...` followed by ✓ or x depending on the outcome of `f`.
+fn step<T>(msg: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    out!("{}...", msg);
+    flush();
+    let ret = f();
+    match &ret {
+        Ok (_) => outln!(" ✓"),
+        Err(_) => outln!(" x"),
+    }
+    flush();
+    ret
+}
+
+// The value of a step that may fail without stopping the run, printing why
+// it failed.
+fn optional<T>(ret: Result<T>) -> Option<T> {
+    match ret {
+        Ok (value) => Some(value),
+        Err(err) => {
+            outln!("    /!\\ {}", err);
+            flush();
+            None
+        }
+    }
+}
+
+// Progress callback printing every 10% of a download.
+fn percent() -> impl FnMut(u64, Option<u64>) {
+    let mut shown = 0;
+    move |done, total| {
+        if let Some(total) = total.filter(|t| *t > 0) {
+            let pct = done * 100 / total / 10 * 10;
+            if pct > shown {
+                shown = pct;
+                print!(" {}%", pct);
+                flush();
+            }
+        }
+    }
+}
+
+fn human(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+fn commit_of(record: &Record) -> Result<Oid> {
+    let commit = record.commit.as_deref().unwrap_or_default();
+    Ok(Oid::from_str(commit)?)
+}
+
+// A working directory of the experiment repository (the kernel tree, or the
+// objtree of out-of-tree builds) or a worktree of it when builds run in
+// parallel.
+struct Workspace {
+    dir: String,
+    git: MyGit,
+    // Where make runs: `dir`, or the kernel tree when `dir` is an objtree.
+    source: String,
+    // The manifest's, with a share of its jobs and O=`dir` in a worktree.
+    build: BuildOptions,
+}
+
+struct Campaign<'a> {
+    manifest: &'a Manifest,
+    // Directory of the experiment repository.
+    repo: &'a str,
+    journal: Mutex<Journal>,
+    // Used to attribute rebuilds to options: the obj-$(CONFIG_X) gates of
+    // the Makefiles first, then the Kconfig directories.
+    owners: (Option<KbuildIndex>, Option<KconfigDirs>),
+}
+
+impl<'a> Campaign<'a> {
+
+    fn journal(&self) -> MutexGuard<'_, Journal> {
+        self.journal.lock().unwrap()
+    }
+
+    // Runs `f` unless the journal says the step is already over, and journals
+    // its outcome. `f` is told whether a previous attempt was interrupted.
+    fn journaled(&self, folder: &str, mutant: &str, kind: Kind,
+                 label: &str, f: impl FnOnce(&Self, bool) -> Result<Record>)
+                 -> Result<Record> {
+        let interrupted = {
+            let mut journal = self.journal();
+            if let Some(record) = journal.finished(folder, mutant, kind) {
+                outln!("{}: already done, skipped", label);
+                flush();
+                return Ok(record.clone());
+            }
+            let interrupted = journal.interrupted(folder, mutant, kind);
+            journal.append(Record::new(folder, mutant, kind, Status::Started))?;
+            interrupted
+        };
+        match f(self, interrupted) {
+            Ok (record) => {
+                self.journal().append(record.clone())?;
+                Ok(record)
+            },
+            Err(err) => {
+                let mut record = Record::new(folder, mutant, kind, Status::Failed);
+                record.error = Some(err.to_string());
+                self.journal().append(record)?;
+                Err(err)
+            }
+        }
+    }
+
+    // Points `branch` at `from`, checks it out and puts `config` in place.
+    // After an interrupted attempt, whatever it left in the tree is dropped.
+    fn prepare(&self, ws: &Workspace, config: &Path, branch: &str, from: Oid,
+               interrupted: bool) -> Result<()> {
+        step(&format!("  │ ├─ Creating new branch {}", branch),
+             || ws.git.reset_branch(branch, from))?;
+        step(&format!("  │ ├─ Checkout to {}", branch), || if interrupted {
+            ws.git.force_checkout(branch)
+        }else {
+            ws.git.checkout(branch)
+        })?;
+        step("  │ ├─ Copying configuration", || {
+            fs::copy(config, validate::config_path(&ws.source, &ws.build))
+                .map_err(|err| Error::io(config, err))
+        })?;
+        Ok(())
+    }
+
+    // Builds the checked out branch and commits the result. A failing build
+    // is reported and committed like any other.
+    fn build_and_commit(&self, ws: &Workspace, label: &str, branch: &str,
+                        msg: &str, last: bool) -> Result<(Oid, BuildResult)> {
+        out!  ("  │ ├─ {}...", label);
+        flush();
+        let result = match lmutib::build(&ws.source, &ws.build,
+                                         &self.manifest.output.join(branch)) {
+            Ok (result) if result.success() => {
+                out!(" ✓");
+                outln!(" {:.2}s", result.wall.as_secs_f64());
+                result
+            },
+            Ok (result) => {
+                outln!(" x");
+                outln!("  │   ‗‗Trace‗‗\n  │   {:?}",
+                       fs::read_to_string(&result.stderr)
+                       .unwrap_or_default().trim());
+                result
+            },
+            Err(err) => {
+                outln!(" x");
+                return Err(err);
+            }
+        };
+        flush();
+        if self.manifest.objtree.is_some() {
+            // Written by make in the objtree, and ignoring everything.
+            let _ = fs::remove_file([&ws.dir, ".gitignore"].join("/"));
+        }
+        let tree = step("  │ ├─ Adding all", || ws.git.add_all())?;
+        let glyph = if last { "└─" } else { "├─" };
+        let commit = step(&format!("  │ {} Committing", glyph),
+                          || ws.git.commit(msg, tree))?;
+        Ok((commit, result))
+    }
+
+    fn built(folder: &str, mutant: &str, kind: Kind, commit: Oid,
+             result: &BuildResult) -> Record {
+        let mut record = Record::new(folder, mutant, kind, Status::Done);
+        record.commit = Some(commit.to_string());
+        record.success = Some(result.success());
+        record.wall = Some(result.wall.as_secs_f64());
+        record
+    }
+
+    fn clean_build(&self, ws: &Workspace, folder: &Folder, mutant: &Mutant,
+                   src_commit: Oid) -> Result<Record> {
+        let config_branch = [&folder.name, &mutant.name, "cb"].join("-");
+        self.journaled(
+            &folder.name, &mutant.name, Kind::Cb, "  │ ├─ Clean build",
+            |c, interrupted| {
+                c.prepare(ws, &mutant.path, &config_branch, src_commit,
+                          interrupted)?;
+                let validation = step("  │ ├─ Olddefconfig", || {
+                    validate::check_mutant(&ws.source, &ws.build,
+                                           &folder.base, &mutant.path)
+                })?;
+                let logdir = c.manifest.output.join(&config_branch);
+                fs::create_dir_all(&logdir)
+                    .map_err(|err| Error::io(&logdir, err))?;
+                validation.write(&logdir.join("olddefconfig"))?;
+                outln!("  │ ├─ Flips: {}, reverted: {}",
+                       validation.flips.len(), validation.reverted.len());
+                flush();
+                let diff_size = lmutib::diffconfig_normalised(
+                    &folder.base, &mutant.path)?.len();
+                if !validation.is_valid()
+                    && c.manifest.reverted == RevertPolicy::Reject {
+                        outln!("  │ └─ Rejected");
+                        flush();
+                        let mut record = Record::new(&folder.name, &mutant.name,
+                                                     Kind::Cb, Status::Rejected);
+                        record.diff_size = Some(diff_size);
+                        return Ok(record);
+                    }
+                let (commit, result) = c.build_and_commit(
+                    ws, "Clean build", &config_branch, "clean build", false)?;
+                let mut record = Self::built(&folder.name, &mutant.name,
+                                             Kind::Cb, commit, &result);
+                record.diff_size = Some(diff_size);
+                Ok(record)
+            })
+    }
+
+    fn incremental_build(&self, ws: &Workspace, folder: &Folder,
+                         mutant: &Mutant, base_cb_commit: Oid)
+                         -> Result<Record> {
+        let base_config_branch = [&folder.name, "base", "cb"].join("-");
+        let config_branch_ib = [&folder.name, &mutant.name, "ib"].join("-");
+        self.journaled(
+            &folder.name, &mutant.name, Kind::Ib, "  │ └─ Incremental build",
+            |c, interrupted| {
+                c.prepare(ws, &mutant.path, &config_branch_ib, base_cb_commit,
+                          interrupted)?;
+                let logdir = c.manifest.output.join(&config_branch_ib);
+                fs::create_dir_all(&logdir)
+                    .map_err(|err| Error::io(&logdir, err))?;
+                let trace = step("  │ ├─ Makefile trace", || {
+                    lmutib::makeni_trace(&ws.source, &ws.build, c.manifest.trace,
+                                         &logdir.join("makeni"))
+                })?;
+                let predicted = lmutib::mkf_ni_trace_total(&trace);
+                outln!("  │ ├─ Total to do: {}", predicted);
+                flush();
+                let (commit, result) = c.build_and_commit(
+                    ws, &format!("Incremental build ({} → {})",
+                                 base_config_branch, config_branch_ib),
+                    &config_branch_ib, "incremental build", false)?;
+                let prediction = Prediction::new(
+                    &trace, &MakeTrace::from_log(&result.stdout)?);
+                prediction.write(&logdir.join("prediction"))?;
+                Breakdown::new(&prediction).write(&logdir.join("breakdown"))?;
+                if c.owners.0.is_some() || c.owners.1.is_some() {
+                    let diff = lmutib::diffconfig_normalised(&folder.base,
+                                                             &mutant.path)?;
+                    let attribution = Attribution::new(
+                        &diff, &prediction.rebuilt, &c.owners);
+                    attribution.write(&logdir.join("attribution"))?;
+                    if let Some((option, n)) = attribution.ranking().first() {
+                        outln!("  │ ├─ Most rebuilt by: {} ({})", option, n);
+                    }
+                }
+                outln!("  │ └─ Rebuilt: {}, false positives: {}, \
+                        false negatives: {}", prediction.rebuilt.len(),
+                       prediction.false_positives.len(),
+                       prediction.false_negatives.len());
+                flush();
+                let mut record = Self::built(&folder.name, &mutant.name,
+                                             Kind::Ib, commit, &result);
+                record.predicted = Some(predicted);
+                record.rebuilt = Some(prediction.rebuilt.len());
+                record.false_positives = Some(prediction.false_positives.len());
+                record.false_negatives = Some(prediction.false_negatives.len());
+                Ok(record)
+            })
+    }
+
+    fn base_build(&self, ws: &Workspace, folder: &Folder, src_commit: Oid)
+                  -> Result<Record> {
+        let base_config_branch = [&folder.name, "base", "cb"].join("-");
+        self.journaled(
+            &folder.name, "base", Kind::Cb, "  │ └─ Clean build",
+            |c, interrupted| {
+                c.prepare(ws, &folder.base, &base_config_branch, src_commit,
+                          interrupted)?;
+                let (commit, result) = c.build_and_commit(
+                    ws, "Clean build", &base_config_branch, "clean build",
+                    true)?;
+                Ok(Self::built(&folder.name, "base", Kind::Cb, commit, &result))
+            })
+    }
+
+    fn run_mutant(&self, ws: &Workspace, folder: &Folder, mutant: &Mutant,
+                  src_commit: Oid, base_cb_commit: Oid) -> Result<()> {
+
+        // CLEAN BUILD
+        // ------------
+
+        outln!("  ├─ Considering {}", mutant.name);
+        flush();
+        let cb = self.clean_build(ws, folder, mutant, src_commit)?;
+        if cb.status == Status::Rejected {
+            return Ok(());
+        }
+
+        // INCREMENTAL BUILD
+        // -----------------
+
+        self.incremental_build(ws, folder, mutant, base_cb_commit)?;
+        Ok(())
+    }
+
+    fn run_folder(&self, main: &Workspace, folder: &Folder, src_commit: Oid) {
+
+        // CLEAN BUILD OF THE BASE CONFIGURATION
+        // -------------------------------------
+
+        outln!("  •  Folder: {}", folder.name);
+        outln!("  ├─ Base configuration: {}", folder.base.display());
+        flush();
+        let base = self.base_build(main, folder, src_commit);
+        let base_cb_commit = match base.and_then(|r| commit_of(&r)) {
+            Ok (oid) => oid,
+            Err(err) => {
+                outln!("  │   /!\\ {}", err);
+                outln!("  └───·");
+                return;
+            }
+        };
+
+        // BUILDS OF THE MUTANTS
+        // ---------------------
+
+        for mutant in &folder.mutants {
+            if let Err(err) = self.run_mutant(main, folder, mutant, src_commit,
+                                              base_cb_commit) {
+                outln!("  │   /!\\ {}", err);
+                flush();
+            }
+        }
+        outln!("  └───·");
+    }
+
+    // Runs `f` in a worktree of its own, <output>/worktrees/<branch>, on
+    // `branch` pointed at `from`. The worktree is deleted afterwards: builds
+    // are committed. Files of the commit `sources` are dated before the
+    // others, so that make sees the checked out artifacts as up to date.
+    fn in_worktree<T>(&self, branch: &str, from: Oid, sources: Oid,
+                      f: impl FnOnce(&Workspace) -> Result<T>) -> Result<T> {
+        let git = MyGit::open(self.repo)?;
+        // Left by an interrupted campaign.
+        if git.worktrees()?.iter().any(|(name, _)| name == branch) {
+            git.remove_worktree(branch)?;
+        }
+        git.reset_branch(branch, from)?;
+        let path = self.manifest.output.join("worktrees").join(branch);
+        let dir = path.to_string_lossy().to_string();
+        let mut build = self.manifest.build.clone()
+            .jobs(self.manifest.build.get_jobs() / self.manifest.parallel);
+        // Out-of-tree, the worktree is an objtree of the shared sources.
+        let source = match &self.manifest.objtree {
+            Some(_) => {
+                build = build.out_dir(&path);
+                self.manifest.kernel.to_string_lossy().to_string()
+            },
+            None => dir.clone(),
+        };
+        let ws = Workspace {
+            dir,
+            git: git.add_worktree(branch, &path, branch)?,
+            source,
+            build,
+        };
+        let now = SystemTime::now();
+        ws.git.set_mtimes(sources, now - Duration::from_secs(3600), now)?;
+        let ret = f(&ws);
+        drop(ws);
+        git.remove_worktree(branch)?;
+        ret
+    }
+
+    // Runs a build of a scheduled campaign and tells whether the builds
+    // depending on it can go on.
+    fn run_job(&self, job: &Job, src_commit: Oid) -> bool {
+        let (folder, mutant, kind) = match job {
+            Job::Base(folder) => (*folder, "base", Kind::Cb),
+            Job::Cb(folder, mutant) => (*folder, &mutant.name[..], Kind::Cb),
+            Job::Ib(folder, mutant) => (*folder, &mutant.name[..], Kind::Ib),
+        };
+        if self.journal().finished(&folder.name, mutant, kind).is_some() {
+            outln!("  │ └─ Already done, skipped");
+            return true;
+        }
+        let suffix = if kind == Kind::Ib { "ib" } else { "cb" };
+        let branch = [folder.name.as_str(), mutant, suffix].join("-");
+        let ret = match job {
+            Job::Base(folder) => self.in_worktree(
+                &branch, src_commit, src_commit,
+                |ws| self.base_build(ws, folder, src_commit)),
+            Job::Cb(folder, mutant) => self.in_worktree(
+                &branch, src_commit, src_commit,
+                |ws| self.clean_build(ws, folder, mutant, src_commit)),
+            Job::Ib(folder, mutant) => {
+                let cb = self.journal().finished(&folder.name, &mutant.name,
+                                                 Kind::Cb).cloned();
+                if cb.map(|r| r.status) == Some(Status::Rejected) {
+                    outln!("  │ └─ Rejected, not built");
+                    return true;
+                }
+                let base = self.journal().finished(&folder.name, "base",
+                                                   Kind::Cb).cloned();
+                base.ok_or_else(|| Error::Schedule(
+                    "no clean build of the base configuration".to_string()))
+                    .and_then(|base| commit_of(&base))
+                    .and_then(|base| self.in_worktree(&branch, base,
+                                                      src_commit, |ws| {
+                        self.incremental_build(ws, folder, mutant, base)
+                    }))
+            },
+        };
+        match ret {
+            Ok (_) => true,
+            Err(err) => {
+                outln!("  │   /!\\ {}", err);
+                false
+            }
+        }
+    }
+
+    // Runs every build of every folder within a budget of `jobs` CPUs, each
+    // build taking jobs/parallel of them. Mutants are clean built as soon as
+    // possible, and incrementally built once their base configuration is.
+    fn run_scheduled(&self, src_commit: Oid) -> Result<()> {
+        let cpus = self.manifest.build.get_jobs() / self.manifest.parallel;
+        let mut schedule = Schedule::new(self.manifest.build.get_jobs());
+        let mut jobs = Vec::new();
+        for folder in &self.manifest.folders {
+            let base = schedule.add(&format!("{} base: clean build",
+                                             folder.name), cpus, &[])?;
+            jobs.push(Job::Base(folder));
+            for mutant in &folder.mutants {
+                let cb = schedule.add(&format!("{} {}: clean build",
+                                               folder.name, mutant.name),
+                                      cpus, &[])?;
+                jobs.push(Job::Cb(folder, mutant));
+                schedule.add(&format!("{} {}: incremental build",
+                                      folder.name, mutant.name),
+                             cpus, &[base, cb])?;
+                jobs.push(Job::Ib(folder, mutant));
+            }
+        }
+        let budget = schedule.get_budget();
+        schedule.run(|i| buffered(|| {
+            outln!("  ├─ {}", schedule.tasks()[i].label);
+            self.run_job(&jobs[i], src_commit)
+        }), |i, state, progress| {
+            let label = &schedule.tasks()[i].label;
+            match state {
+                State::Running => println!(
+                    "  → Started {} ({} running, {}/{} CPUs)", label,
+                    progress.running, progress.cpus, budget),
+                State::Succeeded | State::Failed | State::Cancelled => println!(
+                    "  → [{}/{}] {}: {}", progress.finished(), progress.total,
+                    label, match state {
+                        State::Succeeded => "done",
+                        State::Failed => "failed",
+                        _ => "cancelled",
+                    }),
+                State::Waiting => (),
+            }
+            flush();
+        });
+        Ok(())
+    }
+}
+
+// A build of a scheduled campaign.
+enum Job<'a> {
+    // Clean build of the base configuration.
+    Base(&'a Folder),
+    Cb(&'a Folder, &'a Mutant),
+    Ib(&'a Folder, &'a Mutant),
+}
+
+fn run(manifest: &Manifest) -> Result<()> {
+    let kernel = manifest.kernel.to_str().unwrap();
+    let output = manifest.output.as_path();
+    fs::create_dir_all(output).map_err(|err| Error::io(output, err))?;
+    let journal = Journal::open(&output.join("journal.jsonl"))?;
+
+    println!("┌───────────────────────────┐");
     println!("· Experiment initialization ·");
-    io::stdout().flush().unwrap();
     println!("└───────────────────────────┘");
-    io::stdout().flush().unwrap();
     println!("  → Kernel directory: {}", kernel);
-    io::stdout().flush().unwrap();
-    let _ = fs::remove_file([kernel, ".gitignore"].join("/"));
-    print!  ("  → Initializing git directory...");
-    io::stdout().flush().unwrap();
-    let git = lmutib::MyGit::new(kernel);
-    println!(" ✓");
-    io::stdout().flush().unwrap();
-    print!  ("  → Local git configuration...");
-    io::stdout().flush().unwrap();
-    let _ = git.config("Tux", "None");
-    println!(" ✓");
-    io::stdout().flush().unwrap();
-    print!  ("  → Adding source...");
-    io::stdout().flush().unwrap();
-    let add1 = match git.add_all() {
-        Ok (oid)  => oid,
-        Err(err)  => panic!("\t/!\\ Error adding all.\n\t{:?}", err),
-    };
-    println!(" ✓");
-    io::stdout().flush().unwrap();
-    print!  ("  → Committing sources...");
-    io::stdout().flush().unwrap();
-    let src_commit = match git.commit("source", add1) {
-        Ok (oid) => oid,
-        Err(err) => panic!("\t/!\\ Error commit.\n\t{:?}", err),
+    println!("  → Output directory: {}", output.display());
+    flush();
+    match &manifest.source {
+        _ if manifest.kernel.exists() => (),
+        Some(Source::Release { version, mirror }) => {
+            let mut cache = Cache::new();
+            if let Some(mirror) = mirror {
+                cache = cache.downloader(Downloader::new().mirror(mirror));
+            }
+            step(&format!("  → Installing Linux {} from {}", version,
+                          cache.get_root().display()),
+                 || cache.install(version, &manifest.kernel, percent()))?;
+        },
+        Some(Source::Git { url, rev }) => {
+            let oid = step(&format!("  → Exporting {} from {}", rev, url),
+                           || GitSource::new(url).export(rev, &manifest.kernel))?;
+            println!("  → Kernel commit: {}", oid);
+        },
+        None => (),
+    }
+    // Out-of-tree, only the objtree is versioned and the kernel tree is
+    // left as is.
+    let repo = match &manifest.objtree {
+        Some(objtree) => {
+            println!("  → Objtree: {}", objtree.display());
+            fs::create_dir_all(objtree).map_err(|err| Error::io(objtree, err))?;
+            objtree.to_str().unwrap()
+        },
+        None => {
+            let _ = fs::remove_file([kernel, ".gitignore"].join("/"));
+            kernel
+        },
     };
-    println!(" ✓");
-    io::stdout().flush().unwrap();
+    let git = step("  → Initializing git directory", || MyGit::new(repo))?;
+    if manifest.parallel > 1 {
+        step("  → Pruning worktrees", || git.prune_worktrees())?;
+    }
+    step("  → Local git configuration", || git.config("Tux", "None"))?;
+    let kbuild = optional(step("  → Indexing Kbuild files",
+                               || KbuildIndex::scan(&manifest.kernel)));
+    let kconfig = optional(step("  → Reading Kconfig", || {
+        Kconfig::parse(&manifest.kernel, srcarch(manifest.build.get_arch()))
+    })).map(|kconfig| KconfigDirs::new(&kconfig));
+    let owners = (kbuild, kconfig);
+    let main = Workspace { dir: repo.to_string(), git,
+                           source: kernel.to_string(),
+                           build: manifest.build.clone() };
+    let campaign = Campaign { manifest, repo, journal: Mutex::new(journal),
+                              owners };
+    let source = campaign.journaled("", "", Kind::Source, "  → Sources",
+                                    |_, _| {
+        let add1 = step("  → Adding source", || main.git.add_all())?;
+        let commit = step("  → Committing sources",
+                          || main.git.commit("source", add1))?;
+        let mut record = Record::new("", "", Kind::Source, Status::Done);
+        record.commit = Some(commit.to_string());
+        Ok(record)
+    })?;
+    let src_commit = commit_of(&source)?;
     println!("┌───────────────────────────┐");
-    io::stdout().flush().unwrap();
     println!("·   Starting build tasks    ·");
-    io::stdout().flush().unwrap();
     println!("└───────────────────────────┘");
-    io::stdout().flush().unwrap();
-    let data_configs = Path::new("/home/data-configs");
-
-    for folder in fs::read_dir(data_configs).unwrap().filter(|f| f.is_ok())
-        .map(|f| f.unwrap()) {
-
-            let dir_name = folder.file_name().into_string().unwrap();
-            let base_config_path = [folder.path().to_str().unwrap(),
-                                    "config"].join("/");
-            let base_config_branch = [&dir_name, "base", "cb"]
-                .join("-");
-
-            // CLEAN BUILD OF THE BASE CONFIGURATION
-            // -------------------------------------
-
-            println!("  •  Folder: {}", &dir_name);
-            io::stdout().flush().unwrap();
-            println!("  ├─ Base configuration: {}", base_config_path);
-            io::stdout().flush().unwrap();
-            print!  ("  │ ├─ Creating new branch {}...", base_config_branch);
-            io::stdout().flush().unwrap();
-            git.create_branch(&base_config_branch, src_commit);
-            println!(" ✓");
-            io::stdout().flush().unwrap();
-            print!  ("  │ ├─ Checkout to {}...", base_config_branch);
-            io::stdout().flush().unwrap();
-            git.checkout(&base_config_branch);
-            println!(" ✓");
-            io::stdout().flush().unwrap();
-            print!  ("  │ ├─ Copying configuration...", );
-            io::stdout().flush().unwrap();
-            match fs::copy(base_config_path, [kernel, ".config"].join("/")) {
-                Ok (_) => println!(" ✓"),
-                Err(_) => panic!(" x"),
-            };
-            io::stdout().flush().unwrap();
-            print!  ("  │ ├─ Clean build...");
-            io::stdout().flush().unwrap();
-            match lmutib::build(kernel) {
-                Ok (_) => {
-                    print!(" ✓");
-                    println!(" {}s", fs::read_to_string([kernel, "t+time"]
-                                                        .join("/"))
-                             .unwrap().trim());
-                },
-                Err(_) => {
-                    println!(" x");
-                    println!("  │   ‗‗Trace‗‗\n  │   {:?}",
-                             fs::read_to_string([kernel, "t+error"]
-                                                .join("/")).unwrap().trim());
+    flush();
+
+    if manifest.parallel > 1 {
+        campaign.run_scheduled(src_commit)?;
+    }else {
+        for folder in &manifest.folders {
+            campaign.run_folder(&main, folder, src_commit);
+        }
+    }
+
+    let results = results::from_journal(&campaign.journal());
+    step("  → Writing results", || {
+        results::write_csv(&output.join("results.csv"), &results)?;
+        results::write_json(&output.join("results.json"), &results)
+    })?;
+    Ok(())
+}
+
+fn cache(args: &[String]) -> Result<()> {
+    let cache = Cache::new();
+    match args {
+        [cmd] if cmd == "list" => {
+            for kernel in cache.list()? {
+                let mut has = Vec::new();
+                if kernel.tarball.is_some() {
+                    has.push("tarball");
                 }
-            };
-            io::stdout().flush().unwrap();
-            print!  ("  │ ├─ Adding all...");
-            io::stdout().flush().unwrap();
-            let addbase = match git.add_all() {
-                Ok (oid)  => {
-                    println!(" ✓");
-                    oid
-                },
-                Err(err)  => panic!(" x\n\t{:?}", err),
-            };
-            io::stdout().flush().unwrap();
-            print!  ("  │ └─ Committing...");
-            io::stdout().flush().unwrap();
-            let base_cb_commit = match git.commit("clean build", addbase) {
-                Ok (oid) => {
-                    println!(" ✓");
-                    oid
-                },
-                Err(err) => panic!(" x\n\t{:?}", err),
-            };
-            io::stdout().flush().unwrap();
-
-            // BUILDS OF THE MUTANTS
-            // ---------------------
-
-            for config in fs::read_dir(folder.path()).unwrap().filter(|f| f.is_ok())
-                .map(|f| f.unwrap()){
-                    let file_name = config.file_name().into_string().unwrap();
-                    if file_name.starts_with("___config") {
-                        let config_path = [folder.path().to_str().unwrap(),
-                                           &file_name].join("/");
-                        let config_branch = [&dir_name, &file_name, "cb"]
-                            .join("-");
-
-
-                        // CLEAN BUILD
-                        // ------------
-
-                        println!("  ├─ Considering {}", file_name);
-                        io::stdout().flush().unwrap();
-                        print!  ("  │ ├─ Creating new branch {}...", config_branch);
-                        io::stdout().flush().unwrap();
-                        git.create_branch(&config_branch, src_commit);
-                        println!(" ✓");
-                        io::stdout().flush().unwrap();
-                        print!  ("  │ ├─ Checkout to {}...", config_branch);
-                        io::stdout().flush().unwrap();
-                        git.checkout(&config_branch);
-                        println!(" ✓");
-                        print!  ("  │ ├─ Copying configuration...");
-                        io::stdout().flush().unwrap();
-                        match fs::copy(&config_path, [kernel, ".config"].join("/")) {
-                            Ok (_) => println!(" ✓"),
-                            Err(_) => panic!(" x"),
-                        };
-                        io::stdout().flush().unwrap();
-                        print!  ("  │ ├─ Clean build...");
-                        io::stdout().flush().unwrap();
-                        match lmutib::build(kernel) {
-                            Ok (_) => {
-                                print!(" ✓");
-                                println!(" {}s", fs::read_to_string([kernel, "t+time"]
-                                                                    .join("/"))
-                                         .unwrap().trim());
-                            },
-                            Err(_) => {
-                                println!(" x");
-                                println!("  │   ‗‗Trace‗‗\n  │   {:?}",
-                                         fs::read_to_string([kernel, "t+error"]
-                                                            .join("/")).unwrap().trim());
-                            }
-                        };
-                        io::stdout().flush().unwrap();
-                        print!  ("  │ ├─ Adding all...");
-                        io::stdout().flush().unwrap();
-                        let addcb = match git.add_all() {
-                            Ok (oid)  => {
-                                println!(" ✓");
-                                oid
-                            },
-                            Err(err)  => panic!(" x\n\t{:?}", err),
-                        };
-
-                        print!  ("  │ ├─ Committing...");
-                        io::stdout().flush().unwrap();
-                        let _cb_commit = match git.commit("clean build", addcb) {
-                            Ok (oid) => {
-                                println!(" ✓");
-                                oid
-                            },
-                            Err(err) => panic!(" x\n\t{:?}", err),
-                        };
-
-                        let config_branch_ib = [&dir_name, &file_name, "ib"]
-                            .join("-");
-
-                        // INCREMENTAL BUILD
-                        // -----------------
-
-                        print!  ("  │ ├─ Creating new branch {}...", config_branch_ib);
-                        io::stdout().flush().unwrap();
-                        git.create_branch(&config_branch_ib, base_cb_commit);
-                        println!(" ✓");
-                        io::stdout().flush().unwrap();
-                        print!  ("  │ ├─ Checkout to {}...", config_branch_ib);
-                        io::stdout().flush().unwrap();
-                        git.checkout(&config_branch_ib);
-                        println!(" ✓");
-                        print!  ("  │ ├─ Copying configuration...");
-                        io::stdout().flush().unwrap();
-                        match fs::copy(&config_path, [kernel, ".config"].join("/")) {
-                            Ok (_) => println!(" ✓"),
-                            Err(_) => panic!(" x"),
-                        };
-                        print!  ("  │ ├─ Makefile trace...");
-                        io::stdout().flush().unwrap();
-                        lmutib::makeni_trace(kernel);
-                        println!(" ✓");
-                        io::stdout().flush().unwrap();
-                        println!("  │ ├─ Total to do: {}",
-                                 lmutib::mkf_ni_trace_total(
-                                     lmutib::mkf_ni_trace(
-                                         Path::new(&[kernel, "t+makeni"].join("/")))));
-                        io::stdout().flush().unwrap();
-                        print!  ("  │ ├─ Incremental build ({} → {})...",
-                                 base_config_branch, config_branch_ib);
-                        io::stdout().flush().unwrap();
-                        match lmutib::build(kernel) {
-                            Ok (_) => {
-                                print!(" ✓");
-                                println!(" {}s", fs::read_to_string([kernel, "t+time"]
-                                                                    .join("/"))
-                                         .unwrap().trim());
-                            },
-                            Err(_) => {
-                                println!(" x");
-                                println!("  │   ‗‗Trace‗‗\n  │   {:?}",
-                                         fs::read_to_string([kernel, "t+error"]
-                                                            .join("/")).unwrap().trim());
-                            }
-                        };
-                        io::stdout().flush().unwrap();
-                        print!  ("  │ ├─ Adding all...");
-                        io::stdout().flush().unwrap();
-                        let addib = match git.add_all() {
-                            Ok (oid)  => {
-                                println!(" ✓");
-                                oid
-                            },
-                            Err(err)  => panic!(" x\n\t{:?}", err),
-                        };
-                        io::stdout().flush().unwrap();
-                        print!  ("  │ └─ Committing...");
-                        io::stdout().flush().unwrap();
-                        let _ib_commit = match git.commit("incremental build", addib) {
-                            Ok (oid) => {
-                                println!(" ✓");
-                                oid
-                            },
-                            Err(err) => panic!(" x\n\t{:?}", err),
-                        };
-                        io::stdout().flush().unwrap();
-                    }
+                if kernel.tree.is_some() {
+                    has.push("tree");
                 }
-            println!("  └───·");
+                println!("{:<10} {}  {:<13} {:>10}", kernel.version.to_string(),
+                         &kernel.sha256[..kernel.sha256.len().min(12)],
+                         has.join(", "), human(kernel.size()?));
+            }
+        },
+        [cmd, versions @ ..] if cmd == "prune" => {
+            let mut parsed = Vec::new();
+            for version in versions {
+                parsed.push(KernelVersion::parse(version).ok_or_else(
+                    || Error::download(version, "not a kernel version"))?);
+            }
+            for kernel in cache.prune(&parsed)? {
+                println!("removed {}", kernel.path.display());
+            }
+        },
+        _ => usage(),
+    }
+    Ok(())
+}
+
+fn diffconfig(args: &[String]) -> Result<()> {
+    let mut format = Format::Kernel;
+    let mut normalise = false;
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => match args.next().and_then(|f| Format::parse(f)) {
+                Some(f) => format = f,
+                None => usage(),
+            },
+            "--normalise" => normalise = true,
+            _ => files.push(arg),
         }
+    }
+    match files[..] {
+        [old, new] => {
+            let (old_path, new_path) = (Path::new(old), Path::new(new));
+            let diff = if normalise {
+                lmutib::diffconfig_normalised(old_path, new_path)?
+            }else {
+                lmutib::diffconfig(old_path, new_path)?
+            };
+            print!("{}", diff.format(format, old, new));
+            Ok(())
+        },
+        _ => usage(),
+    }
+}
+
+fn usage() -> ! {
+    let name = env::args().next().unwrap_or_else(|| "lmutib".to_string());
+    eprintln!("usage: {} <manifest.toml|manifest.json>", name);
+    eprintln!("       {} diffconfig [--format kernel|unified|json] \
+               [--normalise] OLD NEW", name);
+    eprintln!("       {} cache list", name);
+    eprintln!("       {} cache prune [VERSION...]", name);
+    process::exit(2);
+}
+
+fn main() {
+
+    let args: Vec<String> = env::args().collect();
+    let ret = match &args[1..] {
+        [cmd, rest @ ..] if cmd == "cache" => cache(rest),
+        [cmd, rest @ ..] if cmd == "diffconfig" => diffconfig(rest),
+        [path] => {
+            let manifest = match Manifest::load(Path::new(path)) {
+                Ok (manifest) => manifest,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                }
+            };
+            run(&manifest)
+        },
+        _ => usage(),
+    };
+    if let Err(err) = ret {
+        eprintln!("\t/!\\ {}", err);
+        process::exit(1);
+    }
 }
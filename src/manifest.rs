@@ -0,0 +1,547 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::build::BuildOptions;
+use crate::download::KernelVersion;
+use crate::error::{Error, Result};
+use crate::mutate::MUTANT_PREFIX;
+use crate::trace::Verbosity;
+
+
+// Experiment manifest, as written by the user (TOML or JSON).
+//
+//   kernel = "/home/linux-5.13"
+//   output = "/home/results"
+//   jobs = 16                           # defaults to the number of CPUs
+//   parallel = 2                        # builds at once, each in its own
+//                                       # worktree with jobs/parallel make
+//                                       # jobs: `jobs` is then the CPU budget
+//                                       # (default 1: one by one in `kernel`)
+//   configs = "/home/data-configs"     # every sub-folder is an experiment
+//   reverted = "reject"                 # mutants olddefconfig undoes flips
+//                                       # of: "record" (default) or "reject"
+//   trace = "v1"                        # make -n verbosity: "quiet"
+//                                       # (default), "v1" or "kbuild_verbose"
+//   objtree = "/home/build"             # builds with O=, and commits only
+//                                       # this directory: `kernel` stays
+//                                       # pristine and shared by all builds
+//
+//   [source]                            # optional: `kernel` is installed
+//   version = "5.13"                    # from the kernel cache when it
+//   mirror = "https://mirrors.edge.kernel.org/pub/linux/kernel"
+//                                       # does not exist yet...
+//   # git = "/home/linux.git"           # ...or exported from a git
+//   # rev = "v6.2-rc1"                  # repository (URL or local path)
+//
+//   [build]                             # optional make arguments
+//   arch = "arm64"
+//   cross_compile = "aarch64-linux-gnu-"
+//   llvm = false
+//   kcflags = "-Wno-error"
+//   targets = ["vmlinux", "modules"]
+//   env = { KBUILD_BUILD_TIMESTAMP = "0" }
+//
+//   [[folder]]                          # and/or explicit folders
+//   path = "/home/other/x86"
+//   base = "config"
+//   mutants = ["___config1", "___config4"]
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawManifest {
+    kernel: PathBuf,
+    output: PathBuf,
+    objtree: Option<PathBuf>,
+    jobs: Option<usize>,
+    #[serde(default = "default_parallel")]
+    parallel: usize,
+    source: Option<RawSource>,
+    #[serde(default)]
+    build: RawBuild,
+    #[serde(default)]
+    reverted: RevertPolicy,
+    #[serde(default)]
+    trace: Verbosity,
+    configs: Option<PathBuf>,
+    #[serde(default = "default_base")]
+    base: String,
+    #[serde(default = "default_mutant_prefix")]
+    mutant_prefix: String,
+    #[serde(default)]
+    folder: Vec<RawFolder>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawFolder {
+    path: PathBuf,
+    name: Option<String>,
+    base: Option<String>,
+    mutants: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSource {
+    version: Option<String>,
+    mirror: Option<String>,
+    git: Option<String>,
+    rev: Option<String>,
+}
+
+impl RawSource {
+
+    // Local repositories are relative to the manifest, like any path.
+    fn resolve(self, root: &Path, problems: &mut Vec<String>)
+               -> Option<Source> {
+        match self {
+            RawSource { version: Some(version), mirror, git: None, rev: None } => {
+                if KernelVersion::parse(&version).is_none() {
+                    problems.push(format!("source: {} is not a kernel version",
+                                          version));
+                }
+                Some(Source::Release { version, mirror })
+            },
+            RawSource { version: None, mirror: None, git: Some(url),
+                        rev: Some(rev) } => {
+                let url = match fs::canonicalize(root.join(&url)) {
+                    Ok(local) => local.to_string_lossy().to_string(),
+                    Err(_) => url,
+                };
+                Some(Source::Git { url, rev })
+            },
+            RawSource { git: Some(_), rev: None, .. } => {
+                problems.push("source: `git` needs a `rev`".to_string());
+                None
+            },
+            _ => {
+                problems.push("source: set either `version` (and `mirror`) \
+                               or `git` and `rev`".to_string());
+                None
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawBuild {
+    arch: Option<String>,
+    cross_compile: Option<String>,
+    out_dir: Option<PathBuf>,
+    #[serde(default)]
+    llvm: bool,
+    kcflags: Option<String>,
+    #[serde(default)]
+    targets: Vec<String>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+}
+
+impl RawBuild {
+
+    fn options(&self, jobs: Option<usize>) -> BuildOptions {
+        let mut options = BuildOptions::new().llvm(self.llvm);
+        if let Some(jobs) = jobs {
+            options = options.jobs(jobs);
+        }
+        if let Some(arch) = &self.arch {
+            options = options.arch(arch);
+        }
+        if let Some(prefix) = &self.cross_compile {
+            options = options.cross_compile(prefix);
+        }
+        if let Some(dir) = &self.out_dir {
+            options = options.out_dir(dir);
+        }
+        if let Some(flags) = &self.kcflags {
+            options = options.kcflags(flags);
+        }
+        for target in &self.targets {
+            options = options.target(target);
+        }
+        for (key, value) in &self.env {
+            options = options.env(key, value);
+        }
+        options
+    }
+}
+
+fn default_parallel() -> usize { 1 }
+
+fn default_base() -> String { "config".to_string() }
+
+fn default_mutant_prefix() -> String { MUTANT_PREFIX.to_string() }
+
+
+// What to do with a mutant when olddefconfig reverts some of its flips.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RevertPolicy {
+    #[default]
+    Record,
+    Reject,
+}
+
+// Where the kernel tree comes from when it is not there yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    // A kernel.org release, through the kernel cache.
+    Release { version: String, mirror: Option<String> },
+    // A tag, branch or commit of a git repository.
+    Git { url: String, rev: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct Mutant {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct Folder {
+    pub name: String,
+    pub base: PathBuf,
+    pub mutants: Vec<Mutant>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub kernel: PathBuf,
+    pub output: PathBuf,
+    // Out-of-tree builds: the O= directory, versioned instead of `kernel`.
+    pub objtree: Option<PathBuf>,
+    pub source: Option<Source>,
+    pub build: BuildOptions,
+    pub parallel: usize,
+    pub reverted: RevertPolicy,
+    pub trace: Verbosity,
+    pub folders: Vec<Folder>,
+}
+
+impl Manifest {
+
+    // Reads and validates the manifest. Every problem found (missing kernel
+    // tree, folder, base or mutant configuration...) is reported at once so
+    // that nothing is built from a half-valid experiment.
+    pub fn load(manifest: &Path) -> Result<Self> {
+        let content = fs::read_to_string(manifest)
+            .map_err(|err| Error::io(manifest, err))?;
+
+        let invalid = |err: &dyn std::fmt::Display|
+            Error::Manifest(format!("{}: {}", manifest.display(), err));
+        let raw: RawManifest = match manifest.extension()
+            .and_then(|e| e.to_str()) {
+                Some("json") => serde_json::from_str(&content)
+                    .map_err(|err| invalid(&err))?,
+                _ => toml::from_str(&content)
+                    .map_err(|err| invalid(&err))?,
+            };
+
+        // Paths are made absolute: builds run from within the kernel tree.
+        let root = manifest.parent().filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let root = fs::canonicalize(root).map_err(|err| Error::io(root, err))?;
+        Self::resolve(raw, &root)
+    }
+
+    fn resolve(raw: RawManifest, root: &Path) -> Result<Self> {
+        let mut problems = Vec::new();
+
+        let source = raw.source
+            .and_then(|source| source.resolve(root, &mut problems));
+
+        let kernel = root.join(&raw.kernel);
+        if !kernel.is_dir() && (source.is_none() || kernel.exists()) {
+            problems.push(format!("kernel: {} is not a directory",
+                                  kernel.display()));
+        }
+        if raw.jobs == Some(0) {
+            problems.push("jobs: must be at least 1".to_string());
+        }
+        if raw.parallel == 0 {
+            problems.push("parallel: must be at least 1".to_string());
+        }
+
+        // The kernel tree is committed as a whole: logs written in it would
+        // be too, and cleaning it up would remove them.
+        let output = root.join(&raw.output);
+        if output.starts_with(&kernel) {
+            problems.push(format!("output: {} is inside the kernel tree",
+                                  output.display()));
+        }
+
+        // Same for the objtree, which is committed as a whole instead.
+        let objtree = raw.objtree.as_ref().map(|dir| root.join(dir));
+        if let Some(objtree) = &objtree {
+            if objtree.starts_with(&kernel) {
+                problems.push(format!("objtree: {} is inside the kernel tree",
+                                      objtree.display()));
+            }
+            if output.starts_with(objtree) {
+                problems.push(format!("output: {} is inside the objtree",
+                                      output.display()));
+            }
+            if raw.build.out_dir.is_some() {
+                problems.push("objtree: cannot be used with build.out_dir"
+                              .to_string());
+            }
+        }
+
+        let mut folders = Vec::new();
+
+        if let Some(configs) = &raw.configs {
+            let configs = root.join(configs);
+            match fs::read_dir(&configs) {
+                Ok(entries) => {
+                    let mut paths: Vec<PathBuf> = entries.flatten()
+                        .map(|e| e.path())
+                        .filter(|p| p.is_dir())
+                        .collect();
+                    paths.sort();
+                    for path in paths {
+                        folders.push(RawFolder {
+                            path, name: None, base: None, mutants: None
+                        });
+                    }
+                },
+                Err(err) => problems.push(format!("configs: {}: {}",
+                                                  configs.display(), err)),
+            }
+        }
+        folders.extend(raw.folder);
+
+        if folders.is_empty() && problems.is_empty() {
+            problems.push("no experiment folder (set `configs` or add a \
+                           [[folder]])".to_string());
+        }
+
+        let mut resolved = Vec::new();
+        for folder in folders {
+            let path = root.join(&folder.path);
+            let name = match folder.name {
+                Some(name) => name,
+                None => match path.file_name() {
+                    Some(name) => name.to_string_lossy().to_string(),
+                    None => {
+                        problems.push(format!("folder: cannot name {}",
+                                              path.display()));
+                        continue;
+                    }
+                }
+            };
+            if !path.is_dir() {
+                problems.push(format!("folder {}: {} is not a directory",
+                                      name, path.display()));
+                continue;
+            }
+
+            let base = path.join(folder.base.as_deref().unwrap_or(&raw.base));
+            if !base.is_file() {
+                problems.push(format!("folder {}: missing base configuration \
+                                       {}", name, base.display()));
+            }
+
+            let names = match folder.mutants {
+                Some(names) => names,
+                None => {
+                    let mut names: Vec<String> = match fs::read_dir(&path) {
+                        Ok(entries) => entries.flatten()
+                            .map(|e| e.file_name().to_string_lossy()
+                                 .to_string())
+                            .filter(|n| n.starts_with(&raw.mutant_prefix))
+                            .collect(),
+                        Err(err) => {
+                            problems.push(format!("folder {}: {}", name, err));
+                            Vec::new()
+                        }
+                    };
+                    names.sort();
+                    names
+                }
+            };
+
+            let mut mutants = Vec::new();
+            for mutant in names {
+                // Journal records and branches of the base configuration
+                // are named after "base".
+                if mutant == "base" {
+                    problems.push(format!("folder {}: a mutant cannot be \
+                                           named base", name));
+                }
+                let mutant_path = path.join(&mutant);
+                if !mutant_path.is_file() {
+                    problems.push(format!("folder {}: missing mutant \
+                                           configuration {}",
+                                          name, mutant_path.display()));
+                }
+                mutants.push(Mutant { name: mutant, path: mutant_path });
+            }
+
+            resolved.push(Folder { name, base, mutants });
+        }
+
+        for (i, folder) in resolved.iter().enumerate() {
+            if resolved[..i].iter().any(|f| f.name == folder.name) {
+                problems.push(format!("folder {}: name used twice",
+                                      folder.name));
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(Error::Manifest(problems.join("\n")));
+        }
+
+        let mut build = raw.build.options(raw.jobs);
+        if let Some(objtree) = &objtree {
+            build = build.out_dir(objtree);
+        }
+
+        Ok(Self {
+            kernel,
+            output,
+            objtree,
+            source,
+            build,
+            parallel: raw.parallel,
+            reverted: raw.reverted,
+            trace: raw.trace,
+            folders: resolved,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn resolve(tmp: &TempDir, manifest: &str) -> Result<Manifest> {
+        Manifest::resolve(toml::from_str(manifest).unwrap(), tmp.path())
+    }
+
+    fn problems(tmp: &TempDir, manifest: &str) -> Vec<String> {
+        match resolve(tmp, manifest) {
+            Err(Error::Manifest(problems)) =>
+                problems.lines().map(|l| l.to_string()).collect(),
+            other => panic!("not a manifest error: {:?}", other.map(|_| ())),
+        }
+    }
+
+    fn tree() -> TempDir {
+        let tmp = TempDir::new();
+        tmp.write("linux/Makefile", "");
+        for folder in ["configs/a", "configs/b"] {
+            tmp.write(&format!("{}/config", folder), "CONFIG_A=y\n");
+            tmp.write(&format!("{}/___config1", folder), "# CONFIG_A is not set\n");
+        }
+        tmp
+    }
+
+    #[test]
+    fn valid() {
+        let tmp = tree();
+        let manifest = resolve(&tmp, r#"
+            kernel = "linux"
+            output = "out"
+            configs = "configs"
+            jobs = 4
+            parallel = 2
+        "#).unwrap();
+        assert_eq!(manifest.kernel, tmp.path().join("linux"));
+        assert_eq!(manifest.output, tmp.path().join("out"));
+        assert_eq!(manifest.parallel, 2);
+        let folders: Vec<(&str, usize)> = manifest.folders.iter()
+            .map(|f| (f.name.as_str(), f.mutants.len())).collect();
+        assert_eq!(folders, [("a", 1), ("b", 1)]);
+        assert_eq!(manifest.folders[0].base, tmp.path().join("configs/a/config"));
+        assert_eq!(manifest.folders[0].mutants[0].name, "___config1");
+    }
+
+    #[test]
+    fn missing_files() {
+        let tmp = tree();
+        let problems = problems(&tmp, r#"
+            kernel = "linux"
+            output = "out"
+            [[folder]]
+            path = "configs/a"
+            base = "nothing"
+            mutants = ["___config1", "___config2"]
+            [[folder]]
+            path = "missing"
+        "#);
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with("folder a: missing base configuration"));
+        assert!(problems[1].starts_with("folder a: missing mutant configuration")
+                && problems[1].ends_with("___config2"));
+        assert!(problems[2].starts_with("folder missing: "));
+    }
+
+    #[test]
+    fn names() {
+        let tmp = tree();
+        tmp.write("configs/b/base", "");
+        let problems = problems(&tmp, r#"
+            kernel = "linux"
+            output = "out"
+            configs = "configs"
+            [[folder]]
+            path = "configs/b"
+            name = "a"
+            mutants = ["base"]
+        "#);
+        assert_eq!(problems, ["folder a: a mutant cannot be named base",
+                              "folder a: name used twice"]);
+    }
+
+    #[test]
+    fn settings() {
+        let tmp = tree();
+        let problems = problems(&tmp, r#"
+            kernel = "linux"
+            output = "linux/out"
+            configs = "configs"
+            jobs = 0
+            parallel = 0
+        "#);
+        assert_eq!(problems, [
+            "jobs: must be at least 1".to_string(),
+            "parallel: must be at least 1".to_string(),
+            format!("output: {} is inside the kernel tree",
+                    tmp.path().join("linux/out").display()),
+        ]);
+        assert!(matches!(resolve(&tmp, "kernel = \"nope\"\noutput = \"out\"\n"),
+                         Err(Error::Manifest(msg))
+                         if msg.starts_with("kernel: ")));
+    }
+
+    #[test]
+    fn formats() {
+        let tmp = tree();
+        let toml = tmp.write("e.toml", r#"
+            kernel = "linux"
+            output = "out"
+            configs = "configs"
+            trace = "v1"
+            [build]
+            arch = "arm64"
+        "#);
+        let json = tmp.write("e.json", r#"{
+            "kernel": "linux", "output": "out", "configs": "configs",
+            "trace": "v1", "build": { "arch": "arm64" }
+        }"#);
+        let (toml, json) = (Manifest::load(&toml).unwrap(),
+                            Manifest::load(&json).unwrap());
+        assert_eq!(toml.kernel, json.kernel);
+        assert_eq!(toml.trace, Verbosity::V1);
+        assert_eq!(json.trace, Verbosity::V1);
+        assert_eq!(toml.build.get_arch(), Some("arm64"));
+        assert_eq!(json.build.get_arch(), Some("arm64"));
+        assert_eq!(toml.folders.len(), json.folders.len());
+        // Unknown fields are errors, in either format.
+        let typo = tmp.write("typo.json", r#"{"kernel": "linux",
+                                              "output": "out", "job": 2}"#);
+        assert!(matches!(Manifest::load(&typo), Err(Error::Manifest(_))));
+    }
+}
@@ -0,0 +1,226 @@
+use std::path::{Path, PathBuf};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use crate::config::{ConfigValue, KernelConfig, Tristate};
+use crate::error::Result;
+use crate::kconfig::{Kconfig, SymbolType};
+use crate::readconfig;
+
+
+// Mutants are looked up with this prefix by the experiment manifest.
+pub const MUTANT_PREFIX: &str = "___config";
+
+// y and n are swapped; m becomes y or n. Bool options cannot be told apart
+// from tristate ones in a .config, so y and n are never turned into m.
+fn flip<R: Rng>(value: Tristate, rng: &mut R) -> Tristate {
+    match value {
+        Tristate::Yes => Tristate::No,
+        Tristate::No => Tristate::Yes,
+        Tristate::Module => *[Tristate::Yes, Tristate::No].choose(rng).unwrap(),
+    }
+}
+
+// Copy of `base` where `k` bool/tristate options, picked at random, are
+// flipped. Fewer options are flipped if `base` has less than `k` of them.
+pub fn mutant<R: Rng>(base: &KernelConfig, k: usize, rng: &mut R)
+                      -> KernelConfig {
+    let candidates: Vec<(&str, Tristate)> = base.iter()
+        .filter_map(|(name, value)| match value {
+            ConfigValue::Tristate(t) => Some((name, *t)),
+            _ => None,
+        })
+        .collect();
+
+    let mut mutant = base.clone();
+    for (name, value) in candidates.choose_multiple(rng, k) {
+        mutant.set(name, ConfigValue::Tristate(flip(*value, rng)));
+    }
+    mutant
+}
+
+// Like `mutant`, but only flips bool/tristate options visible in `base` (a
+// prompt whose dependencies and `if` are met) and not part of a choice; m is
+// only used for tristate options when MODULES is enabled.
+pub fn mutant_with<R: Rng>(base: &KernelConfig, kconfig: &Kconfig, k: usize,
+                           rng: &mut R) -> KernelConfig {
+    let modules = base.get("MODULES")
+        == Some(&ConfigValue::Tristate(Tristate::Yes));
+    let candidates: Vec<(&str, Tristate, SymbolType)> = base.iter()
+        .filter_map(|(name, value)| match (value, kconfig.get(name)) {
+            (ConfigValue::Tristate(t), Some(symbol))
+                if symbol.definitions.iter().all(|d| d.choice.is_none())
+                && kconfig.visible(base, name) =>
+                Some((name, *t, symbol.kind)),
+            _ => None,
+        })
+        .filter(|(_, _, kind)| matches!(kind, SymbolType::Bool
+                                        | SymbolType::Tristate))
+        .collect();
+
+    let mut mutant = base.clone();
+    for (name, value, kind) in candidates.choose_multiple(rng, k) {
+        let flipped = match kind {
+            SymbolType::Tristate if modules => {
+                let others: Vec<Tristate> = [Tristate::Yes, Tristate::Module,
+                                             Tristate::No].into_iter()
+                    .filter(|t| t != value)
+                    .collect();
+                *others.choose(rng).unwrap()
+            },
+            _ => flip(*value, rng),
+        };
+        mutant.set(name, ConfigValue::Tristate(flipped));
+    }
+    mutant
+}
+
+// `n` mutants of `base`. With a seeded RNG (e.g. StdRng::seed_from_u64) the
+// same mutants are produced on every run.
+pub fn mutants<R: Rng>(base: &KernelConfig, n: usize, k: usize, rng: &mut R)
+                       -> Vec<KernelConfig> {
+    (0..n).map(|_| mutant(base, k, rng)).collect()
+}
+
+// Writes the mutants in `dir` as ___config1, ___config2... next to the base
+// configuration, overwriting previous ones.
+pub fn write_mutants(dir: &Path, mutants: &[KernelConfig])
+                     -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for (i, mutant) in mutants.iter().enumerate() {
+        let path = dir.join(format!("{}{}", MUTANT_PREFIX, i + 1));
+        mutant.write(&path)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+// Reads the base configuration `base` and writes `n` mutants of `k` flips
+// each next to it. The same seed always gives the same mutants.
+pub fn generate(base: &Path, n: usize, k: usize, seed: u64)
+                -> Result<Vec<PathBuf>> {
+    let config = readconfig(base)?;
+    let mut rng = StdRng::seed_from_u64(seed);
+    let dir = base.parent().unwrap_or_else(|| Path::new("."));
+    write_mutants(dir, &mutants(&config, n, k, &mut rng))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn base() -> KernelConfig {
+        let mut content = String::from("CONFIG_NAME=\"x\"\nCONFIG_N=3\n");
+        for i in 0..30 {
+            content.push_str(&match i % 3 {
+                0 => format!("CONFIG_O{}=y\n", i),
+                1 => format!("# CONFIG_O{} is not set\n", i),
+                _ => format!("CONFIG_O{}=m\n", i),
+            });
+        }
+        KernelConfig::parse(&content).unwrap()
+    }
+
+    // Options of `mutant` whose value differs from `base`.
+    fn flipped<'a>(base: &KernelConfig, mutant: &'a KernelConfig)
+                   -> Vec<(&'a str, &'a ConfigValue)> {
+        mutant.iter().filter(|(name, value)| base.get(name) != Some(value))
+            .collect()
+    }
+
+    #[test]
+    fn seeded() {
+        let base = base();
+        let run = || mutants(&base, 4, 5, &mut StdRng::seed_from_u64(42))
+            .iter().map(|m| m.to_string()).collect::<Vec<_>>();
+        assert_eq!(run(), run());
+        assert_ne!(run(), mutants(&base, 4, 5, &mut StdRng::seed_from_u64(43))
+                   .iter().map(|m| m.to_string()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn flips() {
+        let base = base();
+        let mut rng = StdRng::seed_from_u64(42);
+        for mutant in mutants(&base, 20, 5, &mut rng) {
+            let flipped = flipped(&base, &mutant);
+            assert_eq!(flipped.len(), 5);
+            for (name, value) in flipped {
+                assert!(name.starts_with('O'), "{} flipped", name);
+                // y and n are never turned into m.
+                if base.get(name) != Some(&ConfigValue::Tristate(
+                    Tristate::Module)) {
+                    assert_ne!(value, &ConfigValue::Tristate(Tristate::Module));
+                }
+            }
+            assert_eq!(mutant.len(), base.len());
+        }
+        // Fewer candidates than k: every one is flipped.
+        let small = KernelConfig::parse("CONFIG_A=y\nCONFIG_B=m\n").unwrap();
+        assert_eq!(flipped(&small, &mutant(&small, 5, &mut rng)).len(), 2);
+    }
+
+    #[test]
+    fn write() {
+        let tmp = TempDir::new();
+        let base = tmp.write("config", &base().to_string());
+        let paths = generate(&base, 3, 2, 42).unwrap();
+        let names: Vec<_> = paths.iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, ["___config1", "___config2", "___config3"]);
+        assert!(paths.iter().all(|p| p.parent() == Some(tmp.path())));
+        let first: Vec<String> = paths.iter()
+            .map(|p| fs::read_to_string(p).unwrap()).collect();
+        generate(&base, 3, 2, 42).unwrap();
+        let second: Vec<String> = paths.iter()
+            .map(|p| fs::read_to_string(p).unwrap()).collect();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn with_kconfig() {
+        let tmp = TempDir::new();
+        tmp.write("Kconfig", "\
+config MODULES
+\tbool
+config A
+\tbool \"a\"
+config T
+\ttristate \"t\"
+config HIDDEN
+\tbool
+config GATED
+\tbool \"gated\" if HIDDEN
+choice
+\tprompt \"c\"
+config C1
+\tbool \"c1\"
+config C2
+\tbool \"c2\"
+endchoice
+");
+        let kconfig = Kconfig::parse(tmp.path(), "x86").unwrap();
+        let base = KernelConfig::parse("\
+CONFIG_MODULES=y
+CONFIG_A=y
+CONFIG_T=y
+# CONFIG_HIDDEN is not set
+# CONFIG_GATED is not set
+CONFIG_C1=y
+# CONFIG_C2 is not set
+").unwrap();
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..10 {
+            let mutant = mutant_with(&base, &kconfig, 5, &mut rng);
+            let flipped = flipped(&base, &mutant);
+            let names: Vec<&str> = flipped.iter().map(|(name, _)| *name)
+                .collect();
+            assert_eq!(names, ["A", "T"]);
+            assert_eq!(mutant.get("A"), Some(&ConfigValue::Tristate(
+                Tristate::No)));
+        }
+    }
+}
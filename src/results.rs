@@ -0,0 +1,107 @@
+use std::fs;
+use std::path::Path;
+use serde::Serialize;
+use crate::error::{Error, Result};
+use crate::journal::{Journal, Kind, Status};
+
+
+// Clean vs incremental build of one mutant. Fields are None for the steps
+// not (yet) done.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MutantResult {
+    pub folder: String,
+    pub mutant: String,
+    pub rejected: bool,
+    pub diff_size: Option<usize>,
+    pub predicted: Option<usize>,
+    pub rebuilt: Option<usize>,
+    pub false_positives: Option<usize>,
+    pub false_negatives: Option<usize>,
+    pub cb_success: Option<bool>,
+    pub cb_wall: Option<f64>,
+    pub cb_commit: Option<String>,
+    pub ib_success: Option<bool>,
+    pub ib_wall: Option<f64>,
+    pub ib_commit: Option<String>,
+}
+
+const COLUMNS: [&str; 14] = [
+    "folder", "mutant", "rejected", "diff_size", "predicted", "rebuilt",
+    "false_positives", "false_negatives",
+    "cb_success", "cb_wall", "cb_commit", "ib_success", "ib_wall", "ib_commit",
+];
+
+fn field<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    }else {
+        field.to_string()
+    }
+}
+
+impl MutantResult {
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.folder.clone(), self.mutant.clone(), self.rejected.to_string(),
+            field(&self.diff_size), field(&self.predicted),
+            field(&self.rebuilt), field(&self.false_positives),
+            field(&self.false_negatives),
+            field(&self.cb_success), field(&self.cb_wall), field(&self.cb_commit),
+            field(&self.ib_success), field(&self.ib_wall), field(&self.ib_commit),
+        ]
+    }
+}
+
+// One result per mutant found in the journal, in the order they were first
+// started. Base configurations are not mutants and are left out.
+pub fn from_journal(journal: &Journal) -> Vec<MutantResult> {
+    let mut results: Vec<MutantResult> = Vec::new();
+    for record in journal.records() {
+        if record.kind == Kind::Source || record.mutant == "base"
+            || results.iter().any(|r| r.folder == record.folder
+                                  && r.mutant == record.mutant) {
+            continue;
+        }
+        let cb = journal.finished(&record.folder, &record.mutant, Kind::Cb);
+        let ib = journal.finished(&record.folder, &record.mutant, Kind::Ib);
+        results.push(MutantResult {
+            folder: record.folder.clone(),
+            mutant: record.mutant.clone(),
+            rejected: cb.map(|r| r.status == Status::Rejected).unwrap_or(false),
+            diff_size: cb.and_then(|r| r.diff_size),
+            predicted: ib.and_then(|r| r.predicted),
+            rebuilt: ib.and_then(|r| r.rebuilt),
+            false_positives: ib.and_then(|r| r.false_positives),
+            false_negatives: ib.and_then(|r| r.false_negatives),
+            cb_success: cb.and_then(|r| r.success),
+            cb_wall: cb.and_then(|r| r.wall),
+            cb_commit: cb.and_then(|r| r.commit.clone()),
+            ib_success: ib.and_then(|r| r.success),
+            ib_wall: ib.and_then(|r| r.wall),
+            ib_commit: ib.and_then(|r| r.commit.clone()),
+        });
+    }
+    results
+}
+
+pub fn write_csv(path: &Path, results: &[MutantResult]) -> Result<()> {
+    let mut csv = COLUMNS.join(",");
+    csv.push('\n');
+    for result in results {
+        let row: Vec<String> = result.row().iter().map(|f| quote(f)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    fs::write(path, csv).map_err(|err| Error::io(path, err))
+}
+
+pub fn write_json(path: &Path, results: &[MutantResult]) -> Result<()> {
+    let json = serde_json::to_string_pretty(results)
+        .map_err(|err| Error::parse(path, 0, err.to_string()))?;
+    fs::write(path, json + "\n").map_err(|err| Error::io(path, err))
+}
@@ -0,0 +1,190 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
+use crate::error::{Error, Result};
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Task {
+    pub label: String,
+    // CPUs the task keeps busy, e.g. its make -j.
+    pub cpus: usize,
+    // Tasks that must succeed before this one starts.
+    pub deps: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Waiting,
+    Running,
+    Succeeded,
+    Failed,
+    // A dependency failed or was cancelled: the task is never run.
+    Cancelled,
+}
+
+// Where a Schedule stands, as given to the progress callback.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub total: usize,
+    pub running: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    // CPUs used by the running tasks.
+    pub cpus: usize,
+}
+
+impl Progress {
+
+    pub fn finished(&self) -> usize {
+        self.succeeded + self.failed + self.cancelled
+    }
+}
+
+// Tasks with dependencies, run concurrently within a budget of CPUs.
+//
+//   let mut schedule = Schedule::new(16);
+//   let base = schedule.add("base", 8, &[])?;
+//   let mutant = schedule.add("mutant", 8, &[base])?;
+//   schedule.run(|task| build(task), |task, state, progress| ...);
+#[derive(Debug, Clone, Default)]
+pub struct Schedule {
+    budget: usize,
+    tasks: Vec<Task>,
+}
+
+impl Schedule {
+
+    pub fn new(budget: usize) -> Self {
+        Self { budget: budget.max(1), tasks: Vec::new() }
+    }
+
+    // Adds a task and returns its index. Dependencies are tasks added
+    // before, so that there cannot be cycles: any other is an error.
+    pub fn add(&mut self, label: &str, cpus: usize, deps: &[usize])
+               -> Result<usize> {
+        if let Some(dep) = deps.iter().find(|dep| **dep >= self.tasks.len()) {
+            return Err(Error::Schedule(format!(
+                "{} depends on task {}, unknown or added after it", label, dep)));
+        }
+        self.tasks.push(Task {
+            label: label.to_string(),
+            cpus: cpus.clamp(1, self.budget),
+            deps: deps.to_vec(),
+        });
+        Ok(self.tasks.len() - 1)
+    }
+
+    pub fn get_budget(&self) -> usize {
+        self.budget
+    }
+
+    pub fn tasks(&self) -> &[Task] {
+        &self.tasks
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    // Runs the tasks, each in a thread of its own, as soon as its
+    // dependencies succeeded and enough of the budget is free, in the order
+    // they were added otherwise. `run` tells whether a task succeeded; a
+    // panic is a failure. `progress` is called from the calling thread
+    // whenever a task changes of state. Returns the final state of each task.
+    pub fn run(&self, run: impl Fn(usize) -> bool + Sync,
+               mut progress: impl FnMut(usize, State, &Progress))
+               -> Vec<State> {
+        let mut states = vec![State::Waiting; self.tasks.len()];
+        let mut status = Progress { total: self.tasks.len(),
+                                    ..Progress::default() };
+        let (sender, receiver) = mpsc::channel();
+        let run = &run;
+        thread::scope(|scope| loop {
+            for (i, task) in self.tasks.iter().enumerate() {
+                if states[i] != State::Waiting {
+                    continue;
+                }
+                let deps = || task.deps.iter().map(|dep| states[*dep]);
+                if deps().any(|s| matches!(s, State::Failed | State::Cancelled)) {
+                    states[i] = State::Cancelled;
+                    status.cancelled += 1;
+                    progress(i, State::Cancelled, &status);
+                }else if deps().all(|s| s == State::Succeeded)
+                    && (status.running == 0
+                        || status.cpus + task.cpus <= self.budget) {
+                        states[i] = State::Running;
+                        status.running += 1;
+                        status.cpus += task.cpus;
+                        progress(i, State::Running, &status);
+                        let sender = sender.clone();
+                        scope.spawn(move || {
+                            let ok = panic::catch_unwind(AssertUnwindSafe(
+                                || run(i))).unwrap_or(false);
+                            let _ = sender.send((i, ok));
+                        });
+                    }
+            }
+            if status.running == 0 {
+                break;
+            }
+            let (i, ok) = match receiver.recv() {
+                Ok (done) => done,
+                Err(_) => break,
+            };
+            status.running -= 1;
+            status.cpus -= self.tasks[i].cpus;
+            if ok {
+                states[i] = State::Succeeded;
+                status.succeeded += 1;
+            }else {
+                states[i] = State::Failed;
+                status.failed += 1;
+            }
+            progress(i, states[i], &status);
+        });
+        states
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add() {
+        let mut schedule = Schedule::new(4);
+        let a = schedule.add("a", 8, &[]).unwrap();
+        assert_eq!(schedule.add("b", 0, &[a]).unwrap(), 1);
+        assert_eq!(schedule.tasks()[0].cpus, 4);
+        assert_eq!(schedule.tasks()[1].cpus, 1);
+        // Forward, self or unknown dependencies.
+        assert!(matches!(schedule.add("c", 1, &[a, 2]),
+                         Err(Error::Schedule(_))));
+        assert!(schedule.add("c", 1, &[7]).is_err());
+        assert_eq!(schedule.len(), 2);
+    }
+
+    #[test]
+    fn run() {
+        let mut schedule = Schedule::new(2);
+        let a = schedule.add("a", 1, &[]).unwrap();
+        let b = schedule.add("b", 1, &[]).unwrap();
+        schedule.add("c", 1, &[a]).unwrap();
+        schedule.add("d", 1, &[a, b]).unwrap();
+        let mut finished = 0;
+        let states = schedule.run(|i| match i {
+            1 => false,
+            3 => panic!("cancelled tasks never run"),
+            _ => true,
+        }, |_, _, progress| finished = progress.finished());
+        assert_eq!(states, [State::Succeeded, State::Failed, State::Succeeded,
+                            State::Cancelled]);
+        assert_eq!(finished, 4);
+    }
+}
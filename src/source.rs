@@ -0,0 +1,167 @@
+use std::path::{Path, PathBuf};
+use git2::build::CheckoutBuilder;
+use git2::{BranchType, Oid, Repository};
+use crate::cache::cache_home;
+use crate::error::{Error, Result};
+
+
+// Kernel sources taken from a git repository (a kernel.org URL, a local
+// clone...) rather than a release tarball: -rc tags, commits between
+// releases. The repository is mirrored once, bare, and trees are exported
+// from it as plain directories that MyGit and build use as usual.
+//
+//   let oid = GitSource::new("https://git.kernel.org/.../linux.git")
+//       .export("v6.2-rc1", Path::new("/home/linux-6.2-rc1"))?;
+#[derive(Debug, Clone)]
+pub struct GitSource {
+    url: String,
+    mirror: PathBuf,
+}
+
+// <cache home>/git/<url with anything but alphanumerics replaced by '_'>.
+pub fn default_mirror(url: &str) -> PathBuf {
+    let name: String = url.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    cache_home().join("git").join(name)
+}
+
+const REFSPECS: [&str; 2] = ["+refs/heads/*:refs/heads/*",
+                             "+refs/tags/*:refs/tags/*"];
+
+impl GitSource {
+
+    pub fn new(url: &str) -> Self {
+        Self { url: url.to_string(), mirror: default_mirror(url) }
+    }
+
+    // Bare repository the sources are mirrored in.
+    pub fn mirror(mut self, dir: &Path) -> Self {
+        self.mirror = dir.to_path_buf();
+        self
+    }
+
+    pub fn get_mirror(&self) -> &Path {
+        &self.mirror
+    }
+
+    // Creates the mirror or fetches every branch and tag into it.
+    pub fn fetch(&self) -> Result<Repository> {
+        let repo = if self.mirror.exists() {
+            Repository::open_bare(&self.mirror)?
+        }else {
+            let repo = Repository::init_bare(&self.mirror)?;
+            repo.remote("origin", &self.url)?;
+            repo
+        };
+        repo.find_remote("origin")?.fetch(&REFSPECS, None, None)?;
+        Ok(repo)
+    }
+
+    // The commit `rev` (tag, branch, commit id...) names. Branches move,
+    // so they are always fetched again; anything else already in the
+    // mirror is used as is.
+    pub fn resolve(&self, rev: &str) -> Result<(Repository, Oid)> {
+        if self.mirror.exists() {
+            let repo = Repository::open_bare(&self.mirror)?;
+            let oid = match repo.revparse_single(rev) {
+                Ok(object) if repo.find_branch(rev, BranchType::Local).is_err()
+                    => Some(object.peel_to_commit()?.id()),
+                _ => None,
+            };
+            if let Some(oid) = oid {
+                return Ok((repo, oid));
+            }
+        }
+        let repo = self.fetch()?;
+        let oid = repo.revparse_single(rev)?.peel_to_commit()?.id();
+        Ok((repo, oid))
+    }
+
+    // Writes the tree of `rev` to `dst`, which must not exist, and returns
+    // the commit it comes from. `dst` is not a git repository.
+    pub fn export(&self, rev: &str, dst: &Path) -> Result<Oid> {
+        if dst.exists() {
+            return Err(Error::io(dst, std::io::ErrorKind::AlreadyExists.into()));
+        }
+        let (repo, oid) = self.resolve(rev)?;
+        let commit = repo.find_commit(oid)?;
+        let mut checkout = CheckoutBuilder::new();
+        checkout.target_dir(dst).force().recreate_missing(true)
+            .update_index(false);
+        repo.checkout_tree(commit.as_object(), Some(&mut checkout))?;
+        Ok(oid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use git2::Signature;
+    use super::*;
+    use crate::testutil::TempDir;
+
+    // Commits `content` as the file `version` on the branch `dev` of `repo`.
+    fn commit(repo: &Repository, content: &str) -> Oid {
+        let mut tree = repo.treebuilder(None).unwrap();
+        tree.insert("version", repo.blob(content.as_bytes()).unwrap(), 0o100644)
+            .unwrap();
+        let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+        let sig = Signature::now("Tux", "None").unwrap();
+        let parent = repo.find_branch("dev", BranchType::Local).ok()
+            .map(|branch| branch.get().peel_to_commit().unwrap());
+        let parents: Vec<_> = parent.iter().collect();
+        let oid = repo.commit(None, &sig, &sig, content, &tree, &parents)
+            .unwrap();
+        repo.branch("dev", &repo.find_commit(oid).unwrap(), true).unwrap();
+        oid
+    }
+
+    // A bare origin with a tag v1 and a branch dev, and its source.
+    fn origin(tmp: &TempDir) -> (Repository, GitSource, Oid) {
+        let path = tmp.path().join("origin.git");
+        let repo = Repository::init_bare(&path).unwrap();
+        let v1 = commit(&repo, "1\n");
+        repo.tag_lightweight("v1", &repo.find_object(v1, None).unwrap(), false)
+            .unwrap();
+        let source = GitSource::new(path.to_str().unwrap())
+            .mirror(&tmp.path().join("mirror"));
+        (repo, source, v1)
+    }
+
+    #[test]
+    fn export() {
+        let tmp = TempDir::new();
+        let (_, source, v1) = origin(&tmp);
+        let dst = tmp.path().join("linux-v1");
+        assert_eq!(source.export("v1", &dst).unwrap(), v1);
+        assert_eq!(fs::read_to_string(dst.join("version")).unwrap(), "1\n");
+        assert!(!dst.join(".git").exists());
+        assert!(Repository::open_bare(source.get_mirror()).is_ok());
+    }
+
+    #[test]
+    fn export_existing() {
+        let tmp = TempDir::new();
+        let (_, source, _) = origin(&tmp);
+        let dst = tmp.path().join("linux");
+        fs::create_dir(&dst).unwrap();
+        assert!(matches!(source.export("v1", &dst), Err(Error::Io { .. })));
+        assert!(!source.get_mirror().exists());
+    }
+
+    #[test]
+    fn resolve() {
+        let tmp = TempDir::new();
+        let (repo, source, v1) = origin(&tmp);
+        assert_eq!(source.resolve("v1").unwrap().1, v1);
+        // Moved upstream: the tag is used as mirrored, the branch is fetched
+        // again.
+        let v2 = commit(&repo, "2\n");
+        repo.tag_lightweight("v1", &repo.find_object(v2, None).unwrap(), true)
+            .unwrap();
+        assert_eq!(source.resolve("v1").unwrap().1, v1);
+        assert_eq!(source.resolve("dev").unwrap().1, v2);
+        assert_eq!(source.resolve(&v2.to_string()).unwrap().1, v2);
+    }
+}
@@ -0,0 +1,41 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// Directory of a test, unique to the process and the call, removed when
+// dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "lmutib-test-{}-{}", std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    // Writes `content` to `file`, relative to the directory, creating the
+    // directories in between.
+    pub fn write(&self, file: &str, content: &str) -> PathBuf {
+        let path = self.0.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
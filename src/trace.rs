@@ -0,0 +1,575 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::error::{Error, Result};
+
+
+// One command make would run, as announced by kbuild:
+//
+//   set -e;  echo '  CC [M]  fs/ext4/inode.o'; gcc ... -o fs/ext4/inode.o ...
+//
+// With V=1, kbuild echoes the command itself rather than a short rule: the
+// rule is then the name of the tool run and the target its output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MakeAction {
+    pub rule: String,
+    pub target: String,
+    pub command: String,
+    // Where make was (make[N]: Entering directory), if it said so.
+    pub directory: Option<PathBuf>,
+}
+
+// End of the single-quoted `echo` argument starting at `text`, kbuild
+// escaping quotes as '\''.
+fn quoted_end(text: &str) -> Option<usize> {
+    let mut start = 0;
+    loop {
+        let quote = start + text[start..].find('\'')?;
+        if text[quote..].starts_with("'\\''") {
+            start = quote + 4;
+        }else {
+            return Some(quote);
+        }
+    }
+}
+
+// Drops the `trap '...' SIGNAL;` kbuild puts in front of commands to delete
+// their target when interrupted, one per signal.
+fn strip_trap(command: &str) -> &str {
+    let mut trimmed = command.trim_start();
+    while let Some(trap) = trimmed.strip_prefix("trap '") {
+        let rest = trap.find('\'').and_then(|end| trap[end..].find(';')
+                                            .map(|semicolon| end + semicolon));
+        match rest {
+            Some(semicolon) => trimmed = trap[semicolon + 1..].trim_start(),
+            None => break,
+        }
+    }
+    trimmed
+}
+
+// How verbose kbuild is while tracing: quiet commands announce a rule and
+// a target (`CC init/main.o`), verbose ones the whole command line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verbosity {
+    #[default]
+    Quiet,
+    // make V=1.
+    V1,
+    // KBUILD_VERBOSE=1 in the environment, for kernels predating V=.
+    KbuildVerbose,
+}
+
+// Splits a shell command into words, honouring quotes and backslashes.
+// Commands are split on unquoted ';'.
+fn shell_split(command: &str) -> Vec<Vec<String>> {
+    let mut commands = vec![Vec::new()];
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                for c in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                    w.push(c);
+                }
+            },
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => w.extend(chars.next()),
+                        c => w.push(c),
+                    }
+                }
+            },
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            ';' => {
+                commands.last_mut().unwrap().extend(word.take());
+                commands.push(Vec::new());
+            },
+            c if c.is_whitespace() =>
+                commands.last_mut().unwrap().extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    commands.last_mut().unwrap().extend(word);
+    commands.retain(|c| !c.is_empty());
+    commands
+}
+
+// Options whose value is the next word, for the tools kbuild runs.
+const WITH_VALUE: [&str; 24] = [
+    "-o", "-I", "-include", "-imacros", "-isystem", "-iquote", "-x", "-MF",
+    "-MT", "-MQ", "-T", "-m", "-e", "-z", "-Map", "--script", "-L", "-O",
+    "-B", "-R", "-j", "--remove-section", "--only-section", "-soname",
+];
+
+// Commands around the actual work.
+const HOUSEKEEPING: [&str; 8] = [
+    "rm", "mkdir", "echo", "printf", "cat", "mv", "true", ":",
+];
+
+// A command line make would run, e.g. gcc's with its flags, input sources
+// and output object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandLine {
+    // Base name of the program: gcc, aarch64-linux-gnu-ld, clang...
+    pub tool: String,
+    pub flags: Vec<String>,
+    pub inputs: Vec<String>,
+    pub output: Option<String>,
+}
+
+impl CommandLine {
+
+    fn from_words(words: &[String]) -> Option<Self> {
+        // Leading VAR=value assignments.
+        let mut words = words.iter()
+            .skip_while(|w| w.contains('=') && !w.starts_with('-'));
+        let program = words.next()?;
+        let tool = program.rsplit('/').next().unwrap_or(program).to_string();
+        let mut flags = Vec::new();
+        let mut positional = Vec::new();
+        let mut output = None;
+        while let Some(word) = words.next() {
+            match word.as_str() {
+                "-o" => output = words.next().cloned(),
+                // Anything after is another command.
+                "|" | "&&" | "||" => break,
+                // The redirection target is not an input.
+                ">" | ">>" | "2>" | "<" => {
+                    words.next();
+                },
+                w if w.starts_with('>') || w.starts_with("2>") => (),
+                w if WITH_VALUE.contains(&w) => match words.next() {
+                    Some(value) => flags.push(format!("{} {}", w, value)),
+                    None => flags.push(w.to_string()),
+                },
+                w if w.starts_with('-') && w.len() > 1 => flags.push(w.to_string()),
+                w => positional.push(w.to_string()),
+            }
+        }
+        // ar MODE ARCHIVE MEMBERS..., objcopy IN [OUT].
+        let ar = tool == "ar" || tool.ends_with("-ar");
+        if ar && output.is_none() && positional.len() >= 2 {
+            flags.push(positional.remove(0));
+            output = Some(positional.remove(0));
+        }else if tool.ends_with("objcopy") && output.is_none()
+            && positional.len() == 2 {
+                output = positional.pop();
+            }
+        Some(Self { tool, flags, inputs: positional, output })
+    }
+
+    // Every command of a shell line (`a; b; c`).
+    pub fn parse_all(command: &str) -> Vec<Self> {
+        shell_split(command).iter()
+            .filter_map(|words| Self::from_words(words))
+            .collect()
+    }
+
+    // The first command of a line that is not housekeeping (rm, mkdir...).
+    pub fn parse(command: &str) -> Option<Self> {
+        let all = Self::parse_all(command);
+        all.iter().find(|c| !HOUSEKEEPING.contains(&c.tool.as_str()))
+            .or_else(|| all.first())
+            .cloned()
+    }
+
+    // C, assembly or Rust sources among the inputs.
+    pub fn sources(&self) -> Vec<&str> {
+        self.inputs.iter().map(|i| i.as_str())
+            .filter(|i| [".c", ".S", ".s", ".rs"].iter().any(|e| i.ends_with(e)))
+            .collect()
+    }
+}
+
+impl MakeAction {
+
+    // Parses a `set -e;  echo '...'; command` line.
+    fn parse(line: &str, directory: Option<&PathBuf>) -> Option<Self> {
+        let echoed = line.strip_prefix("set -e;")?.trim_start()
+            .strip_prefix("echo '")?;
+        let end = quoted_end(echoed)?;
+        let (echo, rest) = (echoed[..end].trim(), &echoed[end + 1..]);
+        let command = strip_trap(rest.trim_start().strip_prefix(';')?)
+            .trim().to_string();
+        Self::from_echo(echo, command, directory)
+    }
+
+    // What kbuild echoes: `CC [M]  fs/ext4/inode.o`, or the command itself
+    // with V=1. `command` is empty when only the echo is known, as in the
+    // log of a quiet build.
+    fn from_echo(echo: &str, command: String, directory: Option<&PathBuf>)
+                 -> Option<Self> {
+        let mut words = echo.split_whitespace();
+        let first = words.next()?;
+        let quiet = first.starts_with(|c: char| c.is_ascii_uppercase())
+            && first.chars().all(|c| c.is_ascii_uppercase()
+                                 || c.is_ascii_digit() || c == '_');
+        let (rule, target) = if quiet {
+            match words.next()? {
+                "[M]" => (format!("{} [M]", first), words.next()?.to_string()),
+                target => (first.to_string(), target.to_string()),
+            }
+        }else {
+            let line = CommandLine::parse(if command.is_empty() { echo }
+                                          else { &command })?;
+            let target = line.output.or_else(|| line.inputs.last().cloned())?;
+            (line.tool, target)
+        };
+        let command = if command.is_empty() && !quiet {
+            echo.to_string()
+        }else {
+            command
+        };
+        Some(Self { rule, target, command, directory: directory.cloned() })
+    }
+
+    // Directory of the target, relative to the tree: "fs/ext4", or "." for
+    // top-level targets.
+    pub fn target_dir(&self) -> &str {
+        match self.target.rfind('/') {
+            Some(slash) => &self.target[..slash],
+            None => ".",
+        }
+    }
+
+    // The command line doing the work, e.g. gcc's rather than the objtool
+    // or fixdep run after it.
+    pub fn command_line(&self) -> Option<CommandLine> {
+        CommandLine::parse(&self.command)
+    }
+
+    pub fn is_object(&self) -> bool {
+        self.target.ends_with(".o") || self.target.ends_with(".ko")
+    }
+}
+
+// What a `make -n` run announced, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MakeTrace {
+    pub actions: Vec<MakeAction>,
+}
+
+impl MakeTrace {
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| Error::io(path, err))?;
+        Ok(Self::parse(&content))
+    }
+
+    // Lines that are not kbuild commands (recursive make invocations, raw
+    // recipes...) are ignored.
+    pub fn parse(content: &str) -> Self {
+        Self::parse_with(content, |line, directory| {
+            MakeAction::parse(line, directory)
+        })
+    }
+
+    // Reads what a real build printed: the echoes of kbuild, indented by
+    // two spaces. Compiler diagnostics and other output are ignored.
+    pub fn parse_log(content: &str) -> Self {
+        Self::parse_with(content, |line, directory| {
+            let echo = line.strip_prefix("  ")
+                .filter(|echo| !echo.starts_with(char::is_whitespace))?;
+            MakeAction::from_echo(echo, String::new(), directory)
+        })
+    }
+
+    pub fn from_log(path: &Path) -> Result<Self> {
+        let content = fs::read(path).map_err(|err| Error::io(path, err))?;
+        Ok(Self::parse_log(&String::from_utf8_lossy(&content)))
+    }
+
+    fn parse_with(content: &str,
+                  action: impl Fn(&str, Option<&PathBuf>) -> Option<MakeAction>)
+                  -> Self {
+        let mut directories: Vec<PathBuf> = Vec::new();
+        let mut actions = Vec::new();
+        for line in content.lines() {
+            if let Some((_, marker)) = line.split_once("]: ")
+                .filter(|(make, _)| make.starts_with("make[")) {
+                    if let Some(dir) = marker.strip_prefix("Entering directory ") {
+                        let dir = dir.trim_matches(|c| c == '\'' || c == '`');
+                        directories.push(PathBuf::from(dir));
+                    }else if marker.starts_with("Leaving directory ") {
+                        directories.pop();
+                    }
+                    continue;
+                }
+            if let Some(action) = action(line, directories.last()) {
+                actions.push(action);
+            }
+        }
+        Self { actions }
+    }
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MakeAction> {
+        self.actions.iter()
+    }
+
+    pub fn per_rule(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for action in &self.actions {
+            *counts.entry(action.rule.as_str()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    // Actions per directory of their target.
+    pub fn per_directory(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for action in &self.actions {
+            *counts.entry(action.target_dir()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    // Targets built, each once, in order.
+    pub fn targets(&self) -> Vec<&str> {
+        let mut seen = HashSet::new();
+        self.actions.iter()
+            .map(|action| action.target.as_str())
+            .filter(|target| seen.insert(*target))
+            .collect()
+    }
+
+    // Object files (.o, .ko) built, each once, in order.
+    pub fn objects(&self) -> Vec<&str> {
+        let mut seen = HashSet::new();
+        self.actions.iter().filter(|a| a.is_object())
+            .map(|action| action.target.as_str())
+            .filter(|target| seen.insert(*target))
+            .collect()
+    }
+}
+
+// Targets a dry run predicted against those the build actually rebuilt.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Prediction {
+    pub predicted: Vec<String>,
+    pub rebuilt: Vec<String>,
+    // Predicted but not rebuilt...
+    pub false_positives: Vec<String>,
+    // ...and rebuilt but not predicted.
+    pub false_negatives: Vec<String>,
+}
+
+impl Prediction {
+
+    pub fn new(predicted: &MakeTrace, actual: &MakeTrace) -> Self {
+        let predicted: Vec<String> = predicted.targets().iter()
+            .map(|t| t.to_string()).collect();
+        let rebuilt: Vec<String> = actual.targets().iter()
+            .map(|t| t.to_string()).collect();
+        let (in_predicted, in_rebuilt): (HashSet<&String>, HashSet<&String>) =
+            (predicted.iter().collect(), rebuilt.iter().collect());
+        let false_positives = predicted.iter()
+            .filter(|t| !in_rebuilt.contains(t)).cloned().collect();
+        let false_negatives = rebuilt.iter()
+            .filter(|t| !in_predicted.contains(t)).cloned().collect();
+        Self { predicted, rebuilt, false_positives, false_negatives }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.false_positives.is_empty() && self.false_negatives.is_empty()
+    }
+
+    // A header line with the counts, then every predicted or rebuilt
+    // target, predicted ones first, each in order: ` target` for one both
+    // predicted and rebuilt, `+target` for a false positive, `-target` for
+    // a false negative.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut report = format!(
+            "# predicted: {}, rebuilt: {}, false positives: {}, \
+             false negatives: {}\n", self.predicted.len(), self.rebuilt.len(),
+            self.false_positives.len(), self.false_negatives.len());
+        let positives: HashSet<&String> = self.false_positives.iter().collect();
+        for target in &self.predicted {
+            let mark = if positives.contains(target) { '+' } else { ' ' };
+            report.push_str(&format!("{}{}\n", mark, target));
+        }
+        for target in &self.false_negatives {
+            report.push_str(&format!("-{}\n", target));
+        }
+        fs::write(path, report).map_err(|err| Error::io(path, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // kbuild's delete-on-interrupt: one trap per signal.
+    const TRAPS: &str = "\
+        trap 'rm -f fs/a.o; trap - HUP; kill -s HUP $$' HUP; \
+        trap 'rm -f fs/a.o; trap - INT; kill -s INT $$' INT; \
+        trap 'rm -f fs/a.o; trap - QUIT; kill -s QUIT $$' QUIT; \
+        trap 'rm -f fs/a.o; trap - TERM; kill -s TERM $$' TERM; \
+        trap 'rm -f fs/a.o; trap - PIPE; kill -s PIPE $$' PIPE;";
+    const GCC: &str = "\
+        gcc -Wp,-MMD,fs/.a.o.d -nostdinc -I./include -c -o fs/a.o fs/a.c";
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(shell_split(r#"gcc -DX='"a b"' -DY="c\"d" e\ f; ; rm -f x"#), [
+            words(&["gcc", "-DX=\"a b\"", "-DY=c\"d", "e f"]),
+            words(&["rm", "-f", "x"]),
+        ]);
+        assert_eq!(shell_split("echo ''"), [words(&["echo", ""])]);
+    }
+
+    #[test]
+    fn quotes() {
+        let echoed = r"  CC      it'\''s.o'; gcc";
+        assert_eq!(&echoed[..quoted_end(echoed).unwrap()], r"  CC      it'\''s.o");
+        assert_eq!(quoted_end("unterminated"), None);
+    }
+
+    #[test]
+    fn traps() {
+        assert_eq!(strip_trap(&format!(" {} {}", TRAPS, GCC)), GCC);
+        assert_eq!(strip_trap(GCC), GCC);
+        assert_eq!(strip_trap("trap 'unterminated"), "trap 'unterminated");
+    }
+
+    #[test]
+    fn command_lines() {
+        let gcc = CommandLine::parse(GCC).unwrap();
+        assert_eq!(gcc.tool, "gcc");
+        assert_eq!(gcc.flags, ["-Wp,-MMD,fs/.a.o.d", "-nostdinc", "-I./include",
+                               "-c"]);
+        assert_eq!(gcc.sources(), ["fs/a.c"]);
+        assert_eq!(gcc.output.as_deref(), Some("fs/a.o"));
+
+        let ar = CommandLine::parse("rm -f fs/built-in.a; \
+                                     ar cDPrST fs/built-in.a fs/a.o fs/b.o")
+            .unwrap();
+        assert_eq!(ar.tool, "ar");
+        assert_eq!(ar.flags, ["cDPrST"]);
+        assert_eq!(ar.output.as_deref(), Some("fs/built-in.a"));
+        assert_eq!(ar.inputs, ["fs/a.o", "fs/b.o"]);
+
+        let objcopy = CommandLine::parse(
+            "/usr/bin/aarch64-linux-gnu-objcopy -O binary -R .note vmlinux Image")
+            .unwrap();
+        assert_eq!(objcopy.tool, "aarch64-linux-gnu-objcopy");
+        assert_eq!(objcopy.flags, ["-O binary", "-R .note"]);
+        assert_eq!((objcopy.inputs.as_slice(), objcopy.output.as_deref()),
+                   (&words(&["vmlinux"])[..], Some("Image")));
+
+        let cpp = CommandLine::parse(
+            "KCFLAGS=-g gcc -E fs/a.c > fs/a.i 2>/dev/null").unwrap();
+        assert_eq!((cpp.tool.as_str(), cpp.inputs.as_slice(), cpp.output),
+                   ("gcc", &words(&["fs/a.c"])[..], None));
+
+        let piped = CommandLine::parse("nm vmlinux | sort > System.map").unwrap();
+        assert_eq!(piped.inputs, ["vmlinux"]);
+        assert_eq!(CommandLine::parse_all("mkdir -p fs; true").len(), 2);
+        assert_eq!(CommandLine::parse("mkdir -p fs").unwrap().tool, "mkdir");
+    }
+
+    #[test]
+    fn quiet() {
+        let action = MakeAction::parse(
+            &format!("set -e;  echo '  CC      fs/a.o'; {} {}", TRAPS, GCC),
+            None).unwrap();
+        assert_eq!((action.rule.as_str(), action.target.as_str()),
+                   ("CC", "fs/a.o"));
+        assert_eq!(action.command, GCC);
+        assert_eq!(action.command_line().unwrap().tool, "gcc");
+        assert_eq!(action.target_dir(), "fs");
+        assert!(action.is_object());
+
+        let module = MakeAction::parse(
+            "set -e;  echo '  CC [M]  drivers/net/dummy.o'; gcc -c \
+             -o drivers/net/dummy.o drivers/net/dummy.c", None).unwrap();
+        assert_eq!((module.rule.as_str(), module.target.as_str()),
+                   ("CC [M]", "drivers/net/dummy.o"));
+
+        let quoted = MakeAction::parse(
+            r"set -e;  echo '  GEN     it'\''s'; touch it\'s", None).unwrap();
+        assert_eq!(quoted.target, r"it'\''s");
+        assert_eq!(quoted.command, r"touch it\'s");
+        assert_eq!(quoted.target_dir(), ".");
+        assert!(MakeAction::parse("make -f ./scripts/Makefile.build obj=fs", None)
+                .is_none());
+    }
+
+    #[test]
+    fn verbose() {
+        for traps in ["", TRAPS] {
+            let action = MakeAction::parse(
+                &format!("set -e;  echo '  {}'; {} {}", GCC, traps, GCC),
+                None).unwrap();
+            assert_eq!((action.rule.as_str(), action.target.as_str()),
+                       ("gcc", "fs/a.o"));
+            assert_eq!(action.command, GCC);
+        }
+        // Without -o, the last input is the target.
+        let action = MakeAction::parse(
+            "set -e;  echo '  ar cDPrST fs/built-in.a fs/a.o'; \
+             ar cDPrST fs/built-in.a fs/a.o", None).unwrap();
+        assert_eq!((action.rule.as_str(), action.target.as_str()),
+                   ("ar", "fs/built-in.a"));
+    }
+
+    #[test]
+    fn directories() {
+        let trace = MakeTrace::parse("\
+make[1]: Entering directory '/src/linux'
+set -e;  echo '  CC      init/main.o'; gcc -c -o init/main.o init/main.c
+make[2]: Entering directory `/build/out'
+set -e;  echo '  CC      fs/a.o'; gcc -c -o fs/a.o fs/a.c
+make[2]: Leaving directory `/build/out'
+set -e;  echo '  LD      vmlinux.o'; ld -r -o vmlinux.o init/main.o fs/a.o
+make[1]: Leaving directory '/src/linux'
+set -e;  echo '  CC      init/main.o'; gcc -c -o init/main.o init/main.c
+");
+        let directories: Vec<Option<&str>> = trace.iter()
+            .map(|a| a.directory.as_ref().map(|d| d.to_str().unwrap()))
+            .collect();
+        assert_eq!(directories, [Some("/src/linux"), Some("/build/out"),
+                                 Some("/src/linux"), None]);
+        assert_eq!(trace.targets(), ["init/main.o", "fs/a.o", "vmlinux.o"]);
+        assert_eq!(trace.objects(), ["init/main.o", "fs/a.o", "vmlinux.o"]);
+        assert_eq!(trace.per_rule(), BTreeMap::from([("CC", 3), ("LD", 1)]));
+        assert_eq!(trace.per_directory(),
+                   BTreeMap::from([(".", 1), ("fs", 1), ("init", 2)]));
+    }
+
+    #[test]
+    fn log() {
+        let trace = MakeTrace::parse_log("  CC      init/main.o
+init/main.c:1:1: warning: unused
+    1 | int x;
+  CC [M]  drivers/net/dummy.o
+  gcc -c -o fs/a.o fs/a.c
+");
+        assert_eq!(trace.targets(), ["init/main.o", "drivers/net/dummy.o",
+                                     "fs/a.o"]);
+        assert_eq!(trace.actions[0].command, "");
+        assert_eq!(trace.actions[2].command, "gcc -c -o fs/a.o fs/a.c");
+    }
+}
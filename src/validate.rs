@@ -0,0 +1,125 @@
+use std::path::{Path, PathBuf};
+use crate::build::BuildOptions;
+use crate::config::{ConfigValue, KernelConfig, Tristate};
+use crate::error::{Error, Result};
+use crate::diff::ConfigDiff;
+use crate::readconfig;
+
+
+// An option whose value in the effective .config differs from the requested
+// one. None stands for an option absent from the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub name: String,
+    pub requested: Option<ConfigValue>,
+    pub effective: Option<ConfigValue>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Validation {
+    // Options the mutant sets differently from its base configuration.
+    pub flips: Vec<String>,
+    // Every requested option changed by olddefconfig...
+    pub changes: Vec<Change>,
+    // ...and, among them, the flips that were undone.
+    pub reverted: Vec<Change>,
+    pub effective: KernelConfig,
+}
+
+impl Validation {
+
+    pub fn is_valid(&self) -> bool {
+        self.reverted.is_empty()
+    }
+
+    // One line per change, `NAME requested -> effective`, reverted flips
+    // being marked with a leading '!'.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let show = |v: &Option<ConfigValue>| match v {
+            Some(v) => v.to_string(),
+            None => "(absent)".to_string(),
+        };
+        let mut record = format!("# flips: {}, changed: {}, reverted: {}\n",
+                                 self.flips.len(), self.changes.len(),
+                                 self.reverted.len());
+        for change in &self.changes {
+            let mark = if self.reverted.contains(change) { '!' } else { ' ' };
+            record.push_str(&format!("{}{} {} -> {}\n", mark, change.name,
+                                     show(&change.requested),
+                                     show(&change.effective)));
+        }
+        std::fs::write(path, record).map_err(|err| Error::io(path, err))
+    }
+}
+
+// An option that is not set and one that is absent behave the same.
+fn same(requested: Option<&ConfigValue>, effective: Option<&ConfigValue>)
+        -> bool {
+    let no = ConfigValue::Tristate(Tristate::No);
+    requested.unwrap_or(&no) == effective.unwrap_or(&no)
+}
+
+// The .config used by make: in the O= directory when there is one.
+pub fn config_path(source: &str, options: &BuildOptions) -> PathBuf {
+    match options.get_out_dir() {
+        Some(dir) => Path::new(source).join(dir).join(".config"),
+        None => Path::new(source).join(".config"),
+    }
+}
+
+// Runs `make olddefconfig` on the .config already in place, as make would
+// silently do (through syncconfig) at the beginning of a build.
+pub fn olddefconfig(source: &str, options: &BuildOptions) -> Result<()> {
+    let output = options.make(source)
+        .arg("olddefconfig")
+        .output()
+        .map_err(|err| Error::io(Path::new("make"), err))?;
+    if !output.status.success() {
+        return Err(Error::Build { source: PathBuf::from(source),
+                                  status: output.status.code() });
+    }
+    Ok(())
+}
+
+// Puts `mutant` in place as the .config of `source`, lets olddefconfig
+// resolve it and compares the effective configuration to the requested one.
+// The .config left in the tree is the effective configuration.
+pub fn check_mutant(source: &str, options: &BuildOptions, base: &Path,
+                    mutant: &Path) -> Result<Validation> {
+    let effective_path = config_path(source, options);
+    std::fs::copy(mutant, &effective_path)
+        .map_err(|err| Error::io(mutant, err))?;
+    olddefconfig(source, options)?;
+
+    let base = readconfig(base)?;
+    let requested = readconfig(mutant)?;
+    let effective = readconfig(&effective_path)?;
+
+    let flips: Vec<String> = requested.iter()
+        .filter(|(name, value)| !same(Some(value), base.get(name)))
+        .map(|(name, _)| name.to_string())
+        .collect();
+
+    // Options absent from the mutant and set by olddefconfig (new symbols,
+    // defaults...) were not requested.
+    let comparison = ConfigDiff::normalised(&requested, &effective);
+    let mut names: Vec<&String> = comparison.changed.keys()
+        .chain(comparison.removed.keys())
+        .filter(|name| requested.contains(name))
+        .collect();
+    names.sort();
+
+    let changes: Vec<Change> = names.into_iter()
+        .map(|name| Change {
+            name: name.to_string(),
+            requested: requested.get(name).cloned(),
+            effective: effective.get(name).cloned(),
+        })
+        .collect();
+    let reverted = changes.iter()
+        .filter(|c| flips.contains(&c.name))
+        .cloned()
+        .collect();
+
+    Ok(Validation { flips, changes, reverted, effective })
+}